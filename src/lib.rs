@@ -0,0 +1,386 @@
+//! The pure domain types: records, their validated fields and [`KernelError`], plus the
+//! [`port::InPort`] transformer trait and the [`in_port_map!`] macro that implements it.
+//!
+//! Nothing here depends on `async_trait` or `tokio`. With default features off the crate is
+//! `no_std` and only needs `alloc`; the `std` feature (on by default) adds [`SystemClock`] and
+//! the panicking [`Data::new`].
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod error {
+    use alloc::string::String;
+
+    /// Implements `core::error::Error`, so `?` lifts it into `anyhow::Error` via anyhow's blanket impl.
+    #[derive(Debug, Clone, thiserror::Error)]
+    pub enum KernelError {
+        #[error("record not found")]
+        NotFound,
+        #[error("record already exists or was modified concurrently")]
+        Conflict,
+        #[error("driver error: {0}")]
+        Driver(String),
+        #[error("validation error: {0}")]
+        Validation(String),
+        #[error("operation timed out")]
+        Timeout,
+        #[error("operation cancelled")]
+        Cancelled,
+        #[error("repository is shutting down")]
+        Unavailable,
+        #[error("batch of {len} exceeds the limit of {max}")]
+        TooLarge { len: usize, max: usize },
+    }
+}
+
+pub mod port {
+    pub trait InPort<I>: 'static + Sync + Send {
+        type Dto;
+        fn emit(&self, input: I) -> Self::Dto;
+
+        /// Feeds this transformer's output into `next`.
+        fn then<T2>(self, next: T2) -> Then<Self, T2>
+            where Self: Sized,
+                  T2: InPort<Self::Dto>
+        {
+            Then { first: self, second: next }
+        }
+    }
+
+    pub struct Then<T1, T2> {
+        first: T1,
+        second: T2
+    }
+
+    impl<I, T1, T2> InPort<I> for Then<T1, T2>
+        where T1: InPort<I>,
+              T2: InPort<T1::Dto>
+    {
+        type Dto = T2::Dto;
+        fn emit(&self, input: I) -> Self::Dto {
+            self.second.emit(self.first.emit(input))
+        }
+    }
+}
+
+/// Implements [`port::InPort`] by moving same-named fields from the input into the DTO.
+///
+/// `field = source` picks a differently named input field; fields after `;` are set from
+/// expressions. A missing source field is reported as "no field `..` on type" at the call site.
+///
+/// ```ignore
+/// in_port_map!(impl InPort<Form> for Port => Dto {
+///     id, name = display_name;
+///     version: 0
+/// });
+/// ```
+#[macro_export]
+macro_rules! in_port_map {
+    (impl InPort<$src:ty> for $port:ty => $dto:path {
+        $($field:ident $(= $from:ident)?),* $(,)?
+        $(; $($fixed:ident : $value:expr),* $(,)?)?
+    }) => {
+        impl $crate::port::InPort<$src> for $port {
+            type Dto = $dto;
+            fn emit(&self, input: $src) -> Self::Dto {
+                $dto {
+                    $($field: $crate::in_port_map!(@from input, $field $(, $from)?),)*
+                    $($($fixed: $value,)*)?
+                }
+            }
+        }
+    };
+    (@from $input:ident, $field:ident) => { $input.$field };
+    (@from $input:ident, $field:ident, $from:ident) => { $input.$from };
+}
+
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+};
+use core::{
+    fmt,
+    hash::{Hash, Hasher},
+    str::FromStr,
+};
+
+use time::OffsetDateTime;
+
+use self::error::KernelError;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DataId(String);
+
+impl DataId {
+    pub const MAX_LEN: usize = 255;
+
+    pub fn new(id: impl Into<String>) -> Result<Self, KernelError> {
+        let id = id.into();
+        if id.is_empty() {
+            return Err(KernelError::Validation("id must not be empty".to_string()));
+        }
+        if id.len() > Self::MAX_LEN {
+            return Err(KernelError::Validation(format!("id must be at most {} bytes", Self::MAX_LEN)));
+        }
+        Ok(Self(id))
+    }
+}
+
+impl FromStr for DataId {
+    type Err = KernelError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s)
+    }
+}
+
+impl fmt::Display for DataId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<str> for DataId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<DataId> for String {
+    fn from(value: DataId) -> Self {
+        value.0
+    }
+}
+
+/// A record name with surrounding whitespace trimmed.
+///
+/// Under the `redact` feature its `Debug` output is masked, so `Data`'s derived `Debug`
+/// (and the driver logs built on it) never show the name. `Display` and `AsRef` are unaffected.
+#[derive(Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(not(feature = "redact"), derive(Debug))]
+pub struct DataName(String);
+
+#[cfg(feature = "redact")]
+impl fmt::Debug for DataName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("DataName").field(&format_args!("***")).finish()
+    }
+}
+
+impl DataName {
+    /// Counted in characters, not bytes.
+    pub const MAX_LEN: usize = 256;
+
+    pub fn new(name: impl Into<String>) -> Result<Self, KernelError> {
+        let name = name.into();
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(KernelError::Validation("name must not be blank".to_string()));
+        }
+        if name.chars().count() > Self::MAX_LEN {
+            return Err(KernelError::Validation(format!("name must be at most {} characters", Self::MAX_LEN)));
+        }
+        Ok(Self(name.to_string()))
+    }
+}
+
+impl FromStr for DataName {
+    type Err = KernelError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s)
+    }
+}
+
+impl fmt::Display for DataName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<str> for DataName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<DataName> for String {
+    fn from(value: DataName) -> Self {
+        value.0
+    }
+}
+
+pub trait Clock: 'static + Send + Sync {
+    fn now(&self) -> OffsetDateTime;
+}
+
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+#[cfg(feature = "std")]
+impl Clock for SystemClock {
+    fn now(&self) -> OffsetDateTime {
+        OffsetDateTime::now_utc()
+    }
+}
+
+/// Always returns the same instant, for deterministic tests.
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock(pub OffsetDateTime);
+
+impl Clock for FixedClock {
+    fn now(&self) -> OffsetDateTime {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, destructure::Destructure)]
+pub struct Data {
+    id: DataId,
+    name: DataName,
+    created_at: OffsetDateTime,
+    deleted_at: Option<OffsetDateTime>,
+    version: u64,
+    /// Opaque client attributes; not validated or interpreted by the domain.
+    extra: BTreeMap<String, String>,
+}
+
+impl Data {
+    /// # Panics
+    /// Panics if the arguments are rejected by [`DataId::new`] or [`DataName::new`].
+    #[cfg(feature = "std")]
+    pub fn new(id: impl Into<String>, name: impl Into<String>) -> Self {
+        let id = DataId::new(id).expect("invalid id");
+        Self::try_new(id, name, &SystemClock).expect("invalid data")
+    }
+
+    pub fn try_new(id: DataId, name: impl Into<String>, clock: &impl Clock) -> Result<Self, KernelError> {
+        let name = DataName::new(name)?;
+        Ok(Self { id, name, created_at: clock.now(), deleted_at: None, version: 0, extra: BTreeMap::new() })
+    }
+
+    pub fn set_name(&mut self, name: impl Into<String>) -> Result<(), KernelError> {
+        self.name = DataName::new(name)?;
+        Ok(())
+    }
+
+    pub fn id(&self) -> &DataId {
+        &self.id
+    }
+
+    pub fn name(&self) -> &DataName {
+        &self.name
+    }
+
+    pub fn created_at(&self) -> OffsetDateTime {
+        self.created_at
+    }
+
+    /// Incremented by the repository on every successful update.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn with_version(mut self, version: u64) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn with_extra(mut self, extra: BTreeMap<String, String>) -> Self {
+        self.extra = extra;
+        self
+    }
+
+    /// Takes the editable fields (name and attributes) from `other`, keeping id, timestamps and version.
+    pub fn with_contents_of(mut self, other: &Data) -> Self {
+        self.name = other.name.clone();
+        self.extra = other.extra.clone();
+        self
+    }
+
+    pub fn extra(&self) -> &BTreeMap<String, String> {
+        &self.extra
+    }
+
+    pub fn deleted_at(&self) -> Option<OffsetDateTime> {
+        self.deleted_at
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+
+    /// Marks the record as soft-deleted. Has no effect if it already is.
+    pub fn mark_deleted(&mut self, clock: &(impl Clock + ?Sized)) {
+        self.deleted_at.get_or_insert_with(|| clock.now());
+    }
+}
+
+// Timestamps are audit metadata and take no part in equality.
+impl PartialEq for Data {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id && self.name == other.name && self.version == other.version && self.extra == other.extra
+    }
+}
+
+impl Eq for Data {}
+
+impl Hash for Data {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+        self.name.hash(state);
+        self.version.hash(state);
+        self.extra.hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use time::OffsetDateTime;
+
+    use super::*;
+
+    // Runs under `cargo test --lib --no-default-features`, where the crate is `no_std`.
+    #[test]
+    fn try_new_validates_without_std() {
+        let clock = FixedClock(OffsetDateTime::UNIX_EPOCH);
+        let data = Data::try_new(DataId::new("a").unwrap(), "  name  ", &clock).unwrap();
+        assert_eq!(data.name().as_ref(), "name");
+        assert_eq!(data.created_at(), OffsetDateTime::UNIX_EPOCH);
+
+        let err = Data::try_new(DataId::new("b").unwrap(), "   ", &clock).unwrap_err();
+        assert!(matches!(err, KernelError::Validation(_)));
+        assert_eq!(err.to_string(), "validation error: name must not be blank");
+    }
+
+    #[test]
+    fn an_empty_id_is_rejected() {
+        assert!(matches!(DataId::new(""), Err(KernelError::Validation(_))));
+    }
+
+    #[test]
+    fn an_empty_name_is_rejected() {
+        let clock = FixedClock(OffsetDateTime::UNIX_EPOCH);
+        let err = Data::try_new(DataId::new("a").unwrap(), "", &clock).unwrap_err();
+        assert!(matches!(err, KernelError::Validation(_)));
+    }
+
+    #[test]
+    fn an_id_over_255_bytes_is_rejected() {
+        assert!(DataId::new("a".repeat(DataId::MAX_LEN)).is_ok());
+        assert!(matches!(DataId::new("a".repeat(DataId::MAX_LEN + 1)), Err(KernelError::Validation(_))));
+    }
+
+    #[test]
+    fn a_name_is_trimmed() {
+        assert_eq!(DataName::new("  x  ").unwrap().as_ref(), "x");
+    }
+
+    #[test]
+    fn a_blank_name_is_rejected() {
+        assert!(matches!(DataName::new(" \t "), Err(KernelError::Validation(_))));
+    }
+}
@@ -1,338 +1,6573 @@
 #![allow(unused)]
 
+/// Generates a `DependOnXxx` trait for a service, or wires a container to serve it itself.
+///
+/// The generated trait is also implemented for `Arc<T>`, so shared containers keep their wiring.
+///
+/// ```ignore
+/// depend_on!(trait DependOnCreateDataService => CreateDataService, create_simple_data_service);
+/// depend_on!(impl DependOnCreateDataService for Handler => CreateDataService, create_simple_data_service);
+/// ```
+macro_rules! depend_on {
+    (trait $depend:ident => $service:ident, $method:ident) => {
+        pub trait $depend: 'static + Send + Sync {
+            type $service: $service;
+            fn $method(&self) -> &Self::$service;
+        }
+
+        impl<T: $depend> $depend for std::sync::Arc<T> {
+            type $service = T::$service;
+            fn $method(&self) -> &Self::$service {
+                (**self).$method()
+            }
+        }
+    };
+    (impl $depend:ident for $container:ty => $service:ident, $method:ident) => {
+        impl $depend for $container {
+            type $service = Self;
+            fn $method(&self) -> &Self::$service {
+                self
+            }
+        }
+    };
+}
+
 /// A.k.a Infrastructure Layer
 pub mod driver {
-    use crate::kernel::{Repository, Data};
+    use std::{
+        collections::{BTreeMap, HashMap, HashSet, VecDeque},
+        fmt,
+        future::Future,
+        sync::{Arc, Mutex, atomic::{AtomicBool, AtomicUsize, Ordering}},
+        time::Duration,
+    };
+
+    use tokio::sync::{Notify, RwLock};
+
+    use futures::{stream, StreamExt};
+
+    use crate::kernel::{Repository, Transaction, Data, DataPatch, DataStream, RequestContext, Clock, SystemClock, error::KernelError};
+
+    #[derive(Clone)]
+    pub struct Pool {
+        url: String,
+        max_connections: u32,
+        state: Arc<PoolState>,
+        sink: Arc<dyn LogSink>,
+        #[cfg(feature = "postgres")]
+        pg: Option<sqlx::PgPool>
+    }
+
+    impl fmt::Debug for Pool {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("Pool")
+                .field("url", &self.url)
+                .field("max_connections", &self.max_connections)
+                .field("state", &self.state)
+                .finish_non_exhaustive()
+        }
+    }
+
+    /// Shared by every clone of a `Pool`, so closing one closes them all.
+    #[derive(Debug, Default)]
+    struct PoolState {
+        closed: AtomicBool,
+        in_flight: AtomicUsize,
+        drained: Notify
+    }
+
+    /// Counts as one in-flight operation until dropped.
+    struct InFlight(Arc<PoolState>);
+
+    impl Drop for InFlight {
+        fn drop(&mut self) {
+            if self.0.in_flight.fetch_sub(1, Ordering::AcqRel) == 1 {
+                self.0.drained.notify_waiters();
+            }
+        }
+    }
+
+    impl Pool {
+        pub fn connect(url: &str, max_connections: u32) -> Result<Pool, KernelError> {
+            if url.is_empty() {
+                return Err(KernelError::Validation("url must not be empty".to_string()));
+            }
+            if max_connections == 0 {
+                return Err(KernelError::Validation("max_connections must be at least 1".to_string()));
+            }
+            Ok(Self {
+                url: url.to_string(),
+                max_connections,
+                state: Arc::default(),
+                sink: default_sink(),
+                #[cfg(feature = "postgres")]
+                pg: None
+            })
+        }
+
+        /// Like `connect`, but also opens a real `sqlx` pool for `PostgresRepository`.
+        #[cfg(feature = "postgres")]
+        pub async fn connect_postgres(url: &str, max_connections: u32) -> Result<Pool, KernelError> {
+            let pool = Self::connect(url, max_connections)?;
+            let pg = sqlx::postgres::PgPoolOptions::new()
+                .max_connections(max_connections)
+                .connect(url).await
+                .map_err(from_sqlx)?;
+            Ok(Self { pg: Some(pg), ..pool })
+        }
+
+        pub fn in_memory() -> Pool {
+            Self {
+                url: "memory://".to_string(),
+                max_connections: 1,
+                state: Arc::default(),
+                sink: default_sink(),
+                #[cfg(feature = "postgres")]
+                pg: None
+            }
+        }
+
+        /// Where the pool and its transactions log; clones made afterwards share the sink.
+        pub fn with_sink(mut self, sink: impl LogSink) -> Self {
+            self.sink = Arc::new(sink);
+            self
+        }
+
+        fn log(&self, args: fmt::Arguments<'_>) {
+            self.sink.log(&args.to_string());
+        }
+
+        pub fn url(&self) -> &str {
+            &self.url
+        }
+
+        pub fn max_connections(&self) -> u32 {
+            self.max_connections
+        }
+
+        /// Fails with `Unavailable` once closed. A Postgres-backed pool runs `SELECT 1`;
+        /// other pools can only check that the url is well formed.
+        pub async fn ping(&self) -> Result<(), KernelError> {
+            self.log(format_args!("ping {}", self.url));
+            if self.is_closed() {
+                return Err(KernelError::Unavailable);
+            }
+            #[cfg(feature = "postgres")]
+            if let Some(pg) = &self.pg {
+                sqlx::query("SELECT 1").execute(pg).await.map_err(from_sqlx)?;
+                return Ok(());
+            }
+            if !self.url.contains("://") {
+                return Err(KernelError::Driver(format!("unreachable: {}", self.url)));
+            }
+            Ok(())
+        }
+
+        /// The transaction counts as in flight until it is committed, rolled back or dropped.
+        pub async fn begin(&self) -> Result<PoolTransaction, KernelError> {
+            let in_flight = self.acquire()?;
+            self.log(format_args!("begin"));
+            Ok(PoolTransaction { pending: Vec::new(), sink: Arc::clone(&self.sink), _in_flight: in_flight })
+        }
+
+        pub fn is_closed(&self) -> bool {
+            self.state.closed.load(Ordering::Acquire)
+        }
+
+        /// Rejects new work with `KernelError::Unavailable` from now on.
+        ///
+        /// The returned future resolves once every operation started before the call has finished.
+        pub fn close(&self) -> impl Future<Output = ()> + Send + 'static {
+            self.state.closed.store(true, Ordering::Release);
+            let state = Arc::clone(&self.state);
+            let sink = Arc::clone(&self.sink);
+            #[cfg(feature = "postgres")]
+            let pg = self.pg.clone();
+            async move {
+                loop {
+                    let drained = state.drained.notified();
+                    if state.in_flight.load(Ordering::Acquire) == 0 {
+                        break;
+                    }
+                    drained.await;
+                }
+                #[cfg(feature = "postgres")]
+                if let Some(pg) = pg {
+                    pg.close().await;
+                }
+                sink.log("pool drained");
+            }
+        }
+
+        fn acquire(&self) -> Result<InFlight, KernelError> {
+            self.state.in_flight.fetch_add(1, Ordering::AcqRel);
+            let in_flight = InFlight(Arc::clone(&self.state));
+            if self.is_closed() {
+                return Err(KernelError::Unavailable);
+            }
+            Ok(in_flight)
+        }
+    }
+
+    pub struct PoolTransaction {
+        pending: Vec<Data>,
+        sink: Arc<dyn LogSink>,
+        _in_flight: InFlight
+    }
+
+    #[async_trait::async_trait]
+    impl Transaction for PoolTransaction {
+        async fn commit(self) -> Result<(), KernelError> {
+            self.sink.log(&format!("commit {:?}", self.pending));
+            Ok(())
+        }
+
+        async fn rollback(self) -> Result<(), KernelError> {
+            self.sink.log(&format!("rollback {:?}", self.pending));
+            Ok(())
+        }
+    }
+    
+    /// Where the driver writes its diagnostics.
+    pub trait LogSink: 'static + Send + Sync {
+        fn log(&self, message: &str);
+    }
+
+    impl<F> LogSink for F
+        where F: Fn(&str) + 'static + Send + Sync
+    {
+        fn log(&self, message: &str) {
+            self(message)
+        }
+    }
+
+    /// Discards every message; the default sink without the `tracing` feature.
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct NoopSink;
+
+    impl LogSink for NoopSink {
+        fn log(&self, _message: &str) {}
+    }
+
+    /// Emits each message as a `tracing` debug event; the default sink with the `tracing` feature.
+    #[cfg(feature = "tracing")]
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct TracingSink;
+
+    #[cfg(feature = "tracing")]
+    impl LogSink for TracingSink {
+        fn log(&self, message: &str) {
+            tracing::debug!(target: "driver", "{}", message);
+        }
+    }
+
+    fn default_sink() -> Arc<dyn LogSink> {
+        #[cfg(feature = "tracing")]
+        return Arc::new(TracingSink);
+        #[cfg(not(feature = "tracing"))]
+        Arc::new(NoopSink)
+    }
+
+    /// Prints `[driver] : <message>` to stdout.
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct StdoutSink;
+
+    impl LogSink for StdoutSink {
+        fn log(&self, message: &str) {
+            println!("[driver] : {}", message);
+        }
+    }
+
+    /// Logs through the sink of its `Pool`.
+    #[derive(Clone)]
+    pub struct DataRepository(pub Pool);
+
+    impl DataRepository {
+        pub fn new(pool: Pool) -> Self {
+            Self(pool)
+        }
+
+        /// Replaces the sink of the underlying pool, so pool and transaction messages go there too.
+        pub fn with_sink(self, sink: impl LogSink) -> Self {
+            Self(self.0.with_sink(sink))
+        }
+
+        fn log(&self, args: fmt::Arguments<'_>) {
+            self.0.log(args);
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Repository for DataRepository {
+        type Tx = PoolTransaction;
+
+        async fn begin(&self) -> Result<Self::Tx, KernelError> {
+            self.0.begin().await
+        }
+
+        #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(id = %data.id())))]
+        async fn create(&self, data: &Data) -> Result<(), KernelError> {
+            let _in_flight = self.0.acquire()?;
+            self.log(format_args!("{:?} ctx={:?}", data, RequestContext::current()));
+            Ok(())
+        }
+
+        async fn create_in(&self, tx: &mut Self::Tx, data: &Data) -> Result<(), KernelError> {
+            let _in_flight = self.0.acquire()?;
+            tx.pending.push(data.clone());
+            Ok(())
+        }
+
+        async fn create_many(&self, data: &[Data]) -> Result<(), KernelError> {
+            let _in_flight = self.0.acquire()?;
+            self.log(format_args!("bulk {:?}", data));
+            Ok(())
+        }
+
+        async fn create_many_returning(&self, data: &[Data]) -> Result<Vec<Result<Data, KernelError>>, KernelError> {
+            self.create_many(data).await?;
+            Ok(data.iter().cloned().map(Ok).collect())
+        }
+
+        async fn find_by_id(&self, id: &str) -> Result<Option<Data>, KernelError> {
+            let _in_flight = self.0.acquire()?;
+            self.log(format_args!("find {}", id));
+            Ok(None)
+        }
+
+        async fn update(&self, data: &Data, expected_version: u64) -> Result<bool, KernelError> {
+            let _in_flight = self.0.acquire()?;
+            self.log(format_args!("update {:?} expected_version={}", data, expected_version));
+            Ok(true)
+        }
+
+        /// Nothing is stored, so the record is reported as `data` under the next version.
+        async fn update_returning(&self, data: &Data, expected_version: u64) -> Result<Option<Data>, KernelError> {
+            self.update(data, expected_version).await?;
+            Ok(Some(data.clone().with_version(expected_version + 1)))
+        }
+
+        async fn delete(&self, id: &str) -> Result<bool, KernelError> {
+            let _in_flight = self.0.acquire()?;
+            self.log(format_args!("delete {}", id));
+            Ok(true)
+        }
+
+        async fn list(&self, offset: u64, limit: u64) -> Result<Vec<Data>, KernelError> {
+            let _in_flight = self.0.acquire()?;
+            self.log(format_args!("list offset={} limit={}", offset, limit));
+            Ok(Vec::new())
+        }
+
+        async fn count(&self) -> Result<u64, KernelError> {
+            let _in_flight = self.0.acquire()?;
+            self.log(format_args!("count"));
+            Ok(0)
+        }
+
+        fn stream_all(&self) -> DataStream {
+            self.log(format_args!("stream all"));
+            Box::pin(stream::empty())
+        }
+
+        async fn find_by_name_prefix(&self, prefix: &str, limit: u64) -> Result<Vec<Data>, KernelError> {
+            let _in_flight = self.0.acquire()?;
+            self.log(format_args!("find by name prefix={} limit={}", prefix, limit));
+            Ok(Vec::new())
+        }
+
+        async fn update_fields(&self, id: &str, patch: DataPatch) -> Result<bool, KernelError> {
+            let _in_flight = self.0.acquire()?;
+            self.log(format_args!("update fields {} {:?}", id, patch));
+            Ok(true)
+        }
+
+        async fn find_by_ids(&self, ids: &[String]) -> Result<Vec<Data>, KernelError> {
+            let _in_flight = self.0.acquire()?;
+            self.log(format_args!("find many {:?}", ids));
+            Ok(Vec::new())
+        }
+    }
+
+    /// Unique violations become `Conflict`; anything unrecognised is reported as `Driver`.
+    #[cfg(any(feature = "postgres", feature = "sqlite"))]
+    fn from_sqlx(e: sqlx::Error) -> KernelError {
+        match e {
+            sqlx::Error::RowNotFound => KernelError::NotFound,
+            sqlx::Error::PoolTimedOut => KernelError::Timeout,
+            sqlx::Error::PoolClosed => KernelError::Unavailable,
+            sqlx::Error::Database(db) if db.is_unique_violation() => KernelError::Conflict,
+            e => KernelError::Driver(e.to_string())
+        }
+    }
+
+    /// The ids a bulk delete reported back, in the order they were asked for.
+    #[cfg(any(feature = "postgres", feature = "sqlite"))]
+    fn in_input_order(ids: &[String], mut deleted: HashSet<String>) -> Vec<String> {
+        ids.iter().filter(|id| deleted.remove(*id)).cloned().collect()
+    }
+
+    /// Reads a `id, name, created_at, version, extra` row back into a record.
+    #[cfg(any(feature = "postgres", feature = "sqlite"))]
+    fn decode<R: sqlx::Row>(row: R) -> Result<Data, KernelError>
+        where for<'r> &'r str: sqlx::ColumnIndex<R>,
+              for<'r> String: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
+              for<'r> time::OffsetDateTime: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
+              for<'r> i64: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
+              for<'r> sqlx::types::Json<BTreeMap<String, String>>: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>
+    {
+        let id: String = row.try_get("id").map_err(from_sqlx)?;
+        let name: String = row.try_get("name").map_err(from_sqlx)?;
+        let created_at: time::OffsetDateTime = row.try_get("created_at").map_err(from_sqlx)?;
+        let version: i64 = row.try_get("version").map_err(from_sqlx)?;
+        let sqlx::types::Json(extra) = row.try_get("extra").map_err(from_sqlx)?;
+        Ok(Data::try_new(id.parse()?, name, &crate::kernel::FixedClock(created_at))?
+            .with_version(version as u64)
+            .with_extra(extra))
+    }
+
+    /// Binds `data` to the five placeholders of an `INSERT` in column order.
+    #[cfg(any(feature = "postgres", feature = "sqlite"))]
+    fn insert<'q, DB: sqlx::Database>(sql: &'q str, data: &'q Data) -> sqlx::query::Query<'q, DB, DB::Arguments<'q>>
+        where &'q str: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+              time::OffsetDateTime: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+              i64: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+              sqlx::types::Json<&'q BTreeMap<String, String>>: sqlx::Encode<'q, DB> + sqlx::Type<DB>
+    {
+        sqlx::query(sql)
+            .bind(data.id().as_ref())
+            .bind(data.name().as_ref())
+            .bind(data.created_at())
+            .bind(data.version() as i64)
+            .bind(sqlx::types::Json(data.extra()))
+    }
+
+    /// One multi-row `INSERT` for `rows` that skips ids already stored and returns the rows it wrote.
+    #[cfg(any(feature = "postgres", feature = "sqlite"))]
+    fn bulk_insert<'q, DB: sqlx::Database>(rows: &'q [Data]) -> sqlx::QueryBuilder<'q, DB>
+        where DB::Arguments<'q>: Default,
+              &'q str: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+              time::OffsetDateTime: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+              i64: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+              sqlx::types::Json<&'q BTreeMap<String, String>>: sqlx::Encode<'q, DB> + sqlx::Type<DB>
+    {
+        let mut query = sqlx::QueryBuilder::new("INSERT INTO data (id, name, created_at, version, extra) ");
+        query.push_values(rows, |mut row, data| {
+            row.push_bind(data.id().as_ref())
+                .push_bind(data.name().as_ref())
+                .push_bind(data.created_at())
+                .push_bind(data.version() as i64)
+                .push_bind(sqlx::types::Json(data.extra()));
+        });
+        query.push(" ON CONFLICT (id) DO NOTHING RETURNING id, name, created_at, version, extra");
+        query
+    }
+
+    /// Pages through `repo.list` `page` rows at a time; the first error ends the stream.
+    #[cfg(any(feature = "postgres", feature = "sqlite"))]
+    fn paged<R: Repository + Clone>(repo: R, page: u64) -> DataStream {
+        Box::pin(stream::unfold(Some(0), move |offset| {
+            let repo = repo.clone();
+            async move {
+                let offset = offset?;
+                match repo.list(offset, page).await {
+                    Ok(rows) => {
+                        let next = (rows.len() as u64 == page).then_some(offset + page);
+                        Some((rows.into_iter().map(Ok).collect::<Vec<_>>(), next))
+                    },
+                    Err(e) => Some((vec![Err(e)], None))
+                }
+            }
+        }).flat_map(stream::iter))
+    }
+
+    /// Stores records in a Postgres table shaped like
+    ///
+    /// ```sql
+    /// CREATE TABLE data (
+    ///     id         TEXT PRIMARY KEY,
+    ///     name       TEXT NOT NULL,
+    ///     created_at TIMESTAMPTZ NOT NULL,
+    ///     version    BIGINT NOT NULL,
+    ///     extra      JSONB NOT NULL
+    /// );
+    /// ```
+    ///
+    /// `delete` removes the row. Statements are prepared once per connection and cached by `sqlx`.
+    #[cfg(feature = "postgres")]
+    #[derive(Debug, Clone)]
+    pub struct PostgresRepository {
+        pool: Pool,
+        db: sqlx::PgPool
+    }
+
+    #[cfg(feature = "postgres")]
+    impl PostgresRepository {
+        const PAGE: u64 = 100;
+
+        /// Rows per bulk `INSERT`, keeping the bind count well under the driver's limit.
+        const BULK_ROWS: usize = 1000;
+
+        const INSERT: &'static str = "INSERT INTO data (id, name, created_at, version, extra) VALUES ($1, $2, $3, $4, $5)";
+
+        const INSERT_RETURNING: &'static str = "INSERT INTO data (id, name, created_at, version, extra) VALUES ($1, $2, $3, $4, $5) \
+            RETURNING id, name, created_at, version, extra";
+
+        /// Fails unless `pool` was opened with `Pool::connect_postgres`.
+        pub fn new(pool: Pool) -> Result<Self, KernelError> {
+            let db = pool.pg.clone()
+                .ok_or_else(|| KernelError::Validation("pool is not backed by postgres".to_string()))?;
+            Ok(Self { pool, db })
+        }
+    }
+
+    #[cfg(feature = "postgres")]
+    pub struct PostgresTransaction(sqlx::Transaction<'static, sqlx::Postgres>, InFlight);
+
+    #[cfg(feature = "postgres")]
+    #[async_trait::async_trait]
+    impl Transaction for PostgresTransaction {
+        async fn commit(self) -> Result<(), KernelError> {
+            self.0.commit().await.map_err(from_sqlx)
+        }
+
+        async fn rollback(self) -> Result<(), KernelError> {
+            self.0.rollback().await.map_err(from_sqlx)
+        }
+    }
+
+    #[cfg(feature = "postgres")]
+    #[async_trait::async_trait]
+    impl Repository for PostgresRepository {
+        type Tx = PostgresTransaction;
+
+        async fn begin(&self) -> Result<Self::Tx, KernelError> {
+            let in_flight = self.pool.acquire()?;
+            Ok(PostgresTransaction(self.db.begin().await.map_err(from_sqlx)?, in_flight))
+        }
+
+        async fn create(&self, data: &Data) -> Result<(), KernelError> {
+            let _in_flight = self.pool.acquire()?;
+            insert(Self::INSERT, data).execute(&self.db).await.map_err(from_sqlx)?;
+            Ok(())
+        }
+
+        async fn create_returning(&self, data: &Data) -> Result<Data, KernelError> {
+            let _in_flight = self.pool.acquire()?;
+            decode(insert(Self::INSERT_RETURNING, data).fetch_one(&self.db).await.map_err(from_sqlx)?)
+        }
+
+        /// One `INSERT .. ON CONFLICT DO NOTHING` per `BULK_ROWS` rows; rows it skipped are reported as `Conflict`.
+        async fn create_many_returning(&self, data: &[Data]) -> Result<Vec<Result<Data, KernelError>>, KernelError> {
+            let _in_flight = self.pool.acquire()?;
+            let mut stored = HashMap::new();
+            for chunk in data.chunks(Self::BULK_ROWS) {
+                for row in bulk_insert(chunk).build().fetch_all(&self.db).await.map_err(from_sqlx)? {
+                    let data = decode(row)?;
+                    stored.insert(data.id().to_string(), data);
+                }
+            }
+            Ok(data.iter().map(|data| stored.remove(data.id().as_ref()).ok_or(KernelError::Conflict)).collect())
+        }
+
+        async fn create_in(&self, tx: &mut Self::Tx, data: &Data) -> Result<(), KernelError> {
+            let _in_flight = self.pool.acquire()?;
+            insert(Self::INSERT, data).execute(&mut *tx.0).await.map_err(from_sqlx)?;
+            Ok(())
+        }
+
+        async fn find_by_id(&self, id: &str) -> Result<Option<Data>, KernelError> {
+            let _in_flight = self.pool.acquire()?;
+            sqlx::query("SELECT id, name, created_at, version, extra FROM data WHERE id = $1")
+                .bind(id)
+                .fetch_optional(&self.db).await
+                .map_err(from_sqlx)?
+                .map(decode)
+                .transpose()
+        }
+
+        async fn update(&self, data: &Data, expected_version: u64) -> Result<bool, KernelError> {
+            Ok(self.update_returning(data, expected_version).await?.is_some())
+        }
+
+        async fn update_returning(&self, data: &Data, expected_version: u64) -> Result<Option<Data>, KernelError> {
+            let _in_flight = self.pool.acquire()?;
+            let updated = sqlx::query("UPDATE data SET name = $1, extra = $2, version = version + 1 WHERE id = $3 AND version = $4 \
+                RETURNING id, name, created_at, version, extra")
+                .bind(data.name().as_ref())
+                .bind(sqlx::types::Json(data.extra()))
+                .bind(data.id().as_ref())
+                .bind(expected_version as i64)
+                .fetch_optional(&self.db).await
+                .map_err(from_sqlx)?;
+            if let Some(row) = updated {
+                return decode(row).map(Some);
+            }
+            let exists = sqlx::query("SELECT 1 FROM data WHERE id = $1")
+                .bind(data.id().as_ref())
+                .fetch_optional(&self.db).await
+                .map_err(from_sqlx)?;
+            match exists {
+                Some(_) => Err(KernelError::Conflict),
+                None => Ok(None)
+            }
+        }
+
+        async fn delete(&self, id: &str) -> Result<bool, KernelError> {
+            let _in_flight = self.pool.acquire()?;
+            let deleted = sqlx::query("DELETE FROM data WHERE id = $1")
+                .bind(id)
+                .execute(&self.db).await
+                .map_err(from_sqlx)?;
+            Ok(deleted.rows_affected() > 0)
+        }
+
+        /// One `DELETE .. WHERE id = ANY($1)` for the whole batch.
+        async fn delete_many(&self, ids: &[String]) -> Result<Vec<String>, KernelError> {
+            let _in_flight = self.pool.acquire()?;
+            let deleted = sqlx::query_scalar("DELETE FROM data WHERE id = ANY($1) RETURNING id")
+                .bind(ids)
+                .fetch_all(&self.db).await
+                .map_err(from_sqlx)?;
+            Ok(in_input_order(ids, deleted.into_iter().collect()))
+        }
+
+        async fn list(&self, offset: u64, limit: u64) -> Result<Vec<Data>, KernelError> {
+            let _in_flight = self.pool.acquire()?;
+            sqlx::query("SELECT id, name, created_at, version, extra FROM data ORDER BY id OFFSET $1 LIMIT $2")
+                .bind(offset as i64)
+                .bind(limit as i64)
+                .fetch_all(&self.db).await
+                .map_err(from_sqlx)?
+                .into_iter()
+                .map(decode)
+                .collect()
+        }
+
+        async fn count(&self) -> Result<u64, KernelError> {
+            let _in_flight = self.pool.acquire()?;
+            let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM data")
+                .fetch_one(&self.db).await
+                .map_err(from_sqlx)?;
+            Ok(count as u64)
+        }
+
+        async fn find_by_name_prefix(&self, prefix: &str, limit: u64) -> Result<Vec<Data>, KernelError> {
+            let _in_flight = self.pool.acquire()?;
+            sqlx::query("SELECT id, name, created_at, version, extra FROM data WHERE starts_with(name, $1) ORDER BY id LIMIT $2")
+                .bind(prefix)
+                .bind(limit as i64)
+                .fetch_all(&self.db).await
+                .map_err(from_sqlx)?
+                .into_iter()
+                .map(decode)
+                .collect()
+        }
+
+        /// Pages through `list` `PAGE` rows at a time; the first error ends the stream.
+        fn stream_all(&self) -> DataStream {
+            paged(self.clone(), Self::PAGE)
+        }
+    }
+
+    /// Stores records in SQLite, creating the `data` table on connect if it is missing.
+    ///
+    /// `delete` removes the row. Meant for CI and local development rather than production.
+    #[cfg(feature = "sqlite")]
+    #[derive(Debug, Clone)]
+    pub struct SqliteRepository {
+        pool: Pool,
+        db: sqlx::SqlitePool
+    }
+
+    #[cfg(feature = "sqlite")]
+    impl SqliteRepository {
+        const SCHEMA: &'static str = "CREATE TABLE IF NOT EXISTS data (
+            id         TEXT PRIMARY KEY,
+            name       TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            version    INTEGER NOT NULL,
+            extra      TEXT NOT NULL
+        )";
+
+        const PAGE: u64 = 100;
+
+        /// Rows per bulk `INSERT`, keeping the bind count well under the driver's limit.
+        const BULK_ROWS: usize = 1000;
+
+        const INSERT: &'static str = "INSERT INTO data (id, name, created_at, version, extra) VALUES (?, ?, ?, ?, ?)";
+
+        const INSERT_RETURNING: &'static str = "INSERT INTO data (id, name, created_at, version, extra) VALUES (?, ?, ?, ?, ?) \
+            RETURNING id, name, created_at, version, extra";
+
+        /// Opens e.g. `sqlite://data.db?mode=rwc`, creating the schema if needed.
+        pub async fn connect(url: &str, max_connections: u32) -> Result<Self, KernelError> {
+            let pool = Pool::connect(url, max_connections)?;
+            let db = sqlx::sqlite::SqlitePoolOptions::new()
+                .max_connections(max_connections)
+                .connect(url).await
+                .map_err(from_sqlx)?;
+            Self::init(pool, db).await
+        }
+
+        /// A private database that lives as long as the repository and its clones.
+        ///
+        /// Every SQLite connection to `:memory:` sees its own database, so this keeps exactly one open.
+        pub async fn in_memory() -> Result<Self, KernelError> {
+            let db = sqlx::sqlite::SqlitePoolOptions::new()
+                .max_connections(1)
+                .min_connections(1)
+                .idle_timeout(None)
+                .max_lifetime(None)
+                .connect("sqlite::memory:").await
+                .map_err(from_sqlx)?;
+            Self::init(Pool::in_memory(), db).await
+        }
+
+        async fn init(pool: Pool, db: sqlx::SqlitePool) -> Result<Self, KernelError> {
+            sqlx::query(Self::SCHEMA).execute(&db).await.map_err(from_sqlx)?;
+            Ok(Self { pool, db })
+        }
+
+    }
+
+    #[cfg(feature = "sqlite")]
+    pub struct SqliteTransaction(sqlx::Transaction<'static, sqlx::Sqlite>, InFlight);
+
+    #[cfg(feature = "sqlite")]
+    #[async_trait::async_trait]
+    impl Transaction for SqliteTransaction {
+        async fn commit(self) -> Result<(), KernelError> {
+            self.0.commit().await.map_err(from_sqlx)
+        }
+
+        async fn rollback(self) -> Result<(), KernelError> {
+            self.0.rollback().await.map_err(from_sqlx)
+        }
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[async_trait::async_trait]
+    impl Repository for SqliteRepository {
+        type Tx = SqliteTransaction;
+
+        async fn begin(&self) -> Result<Self::Tx, KernelError> {
+            let in_flight = self.pool.acquire()?;
+            Ok(SqliteTransaction(self.db.begin().await.map_err(from_sqlx)?, in_flight))
+        }
+
+        async fn create(&self, data: &Data) -> Result<(), KernelError> {
+            let _in_flight = self.pool.acquire()?;
+            insert(Self::INSERT, data).execute(&self.db).await.map_err(from_sqlx)?;
+            Ok(())
+        }
+
+        async fn create_returning(&self, data: &Data) -> Result<Data, KernelError> {
+            let _in_flight = self.pool.acquire()?;
+            decode(insert(Self::INSERT_RETURNING, data).fetch_one(&self.db).await.map_err(from_sqlx)?)
+        }
+
+        /// One `INSERT .. ON CONFLICT DO NOTHING` per `BULK_ROWS` rows; rows it skipped are reported as `Conflict`.
+        async fn create_many_returning(&self, data: &[Data]) -> Result<Vec<Result<Data, KernelError>>, KernelError> {
+            let _in_flight = self.pool.acquire()?;
+            let mut stored = HashMap::new();
+            for chunk in data.chunks(Self::BULK_ROWS) {
+                for row in bulk_insert(chunk).build().fetch_all(&self.db).await.map_err(from_sqlx)? {
+                    let data = decode(row)?;
+                    stored.insert(data.id().to_string(), data);
+                }
+            }
+            Ok(data.iter().map(|data| stored.remove(data.id().as_ref()).ok_or(KernelError::Conflict)).collect())
+        }
+
+        async fn create_in(&self, tx: &mut Self::Tx, data: &Data) -> Result<(), KernelError> {
+            let _in_flight = self.pool.acquire()?;
+            insert(Self::INSERT, data).execute(&mut *tx.0).await.map_err(from_sqlx)?;
+            Ok(())
+        }
+
+        async fn find_by_id(&self, id: &str) -> Result<Option<Data>, KernelError> {
+            let _in_flight = self.pool.acquire()?;
+            sqlx::query("SELECT id, name, created_at, version, extra FROM data WHERE id = ?")
+                .bind(id)
+                .fetch_optional(&self.db).await
+                .map_err(from_sqlx)?
+                .map(decode)
+                .transpose()
+        }
+
+        async fn update(&self, data: &Data, expected_version: u64) -> Result<bool, KernelError> {
+            Ok(self.update_returning(data, expected_version).await?.is_some())
+        }
+
+        async fn update_returning(&self, data: &Data, expected_version: u64) -> Result<Option<Data>, KernelError> {
+            let _in_flight = self.pool.acquire()?;
+            let updated = sqlx::query("UPDATE data SET name = ?, extra = ?, version = version + 1 WHERE id = ? AND version = ? \
+                RETURNING id, name, created_at, version, extra")
+                .bind(data.name().as_ref())
+                .bind(sqlx::types::Json(data.extra()))
+                .bind(data.id().as_ref())
+                .bind(expected_version as i64)
+                .fetch_optional(&self.db).await
+                .map_err(from_sqlx)?;
+            if let Some(row) = updated {
+                return decode(row).map(Some);
+            }
+            let exists = sqlx::query("SELECT 1 FROM data WHERE id = ?")
+                .bind(data.id().as_ref())
+                .fetch_optional(&self.db).await
+                .map_err(from_sqlx)?;
+            match exists {
+                Some(_) => Err(KernelError::Conflict),
+                None => Ok(None)
+            }
+        }
+
+        async fn delete(&self, id: &str) -> Result<bool, KernelError> {
+            let _in_flight = self.pool.acquire()?;
+            let deleted = sqlx::query("DELETE FROM data WHERE id = ?")
+                .bind(id)
+                .execute(&self.db).await
+                .map_err(from_sqlx)?;
+            Ok(deleted.rows_affected() > 0)
+        }
+
+        /// One `DELETE .. WHERE id IN (..)` per `BULK_ROWS` ids.
+        async fn delete_many(&self, ids: &[String]) -> Result<Vec<String>, KernelError> {
+            let _in_flight = self.pool.acquire()?;
+            let mut deleted = HashSet::new();
+            for chunk in ids.chunks(Self::BULK_ROWS) {
+                let mut query = sqlx::QueryBuilder::new("DELETE FROM data WHERE id IN (");
+                let mut list = query.separated(", ");
+                for id in chunk {
+                    list.push_bind(id);
+                }
+                query.push(") RETURNING id");
+                deleted.extend(query.build_query_scalar::<String>().fetch_all(&self.db).await.map_err(from_sqlx)?);
+            }
+            Ok(in_input_order(ids, deleted))
+        }
+
+        async fn list(&self, offset: u64, limit: u64) -> Result<Vec<Data>, KernelError> {
+            let _in_flight = self.pool.acquire()?;
+            sqlx::query("SELECT id, name, created_at, version, extra FROM data ORDER BY id LIMIT ? OFFSET ?")
+                .bind(limit as i64)
+                .bind(offset as i64)
+                .fetch_all(&self.db).await
+                .map_err(from_sqlx)?
+                .into_iter()
+                .map(decode)
+                .collect()
+        }
+
+        async fn count(&self) -> Result<u64, KernelError> {
+            let _in_flight = self.pool.acquire()?;
+            let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM data")
+                .fetch_one(&self.db).await
+                .map_err(from_sqlx)?;
+            Ok(count as u64)
+        }
+
+        async fn find_by_name_prefix(&self, prefix: &str, limit: u64) -> Result<Vec<Data>, KernelError> {
+            let _in_flight = self.pool.acquire()?;
+            sqlx::query("SELECT id, name, created_at, version, extra FROM data WHERE substr(name, 1, length(?1)) = ?1 ORDER BY id LIMIT ?2")
+                .bind(prefix)
+                .bind(limit as i64)
+                .fetch_all(&self.db).await
+                .map_err(from_sqlx)?
+                .into_iter()
+                .map(decode)
+                .collect()
+        }
+
+        /// Pages through `list` `PAGE` rows at a time; the first error ends the stream.
+        fn stream_all(&self) -> DataStream {
+            paged(self.clone(), Self::PAGE)
+        }
+    }
+
+    /// Stores records in process memory. Clones share the same store.
+    #[derive(Clone)]
+    pub struct InMemoryRepository {
+        store: Arc<RwLock<HashMap<String, Data>>>,
+        soft_delete: bool,
+        allow_overwrite: bool,
+        clock: Arc<dyn Clock>
+    }
+
+    impl Default for InMemoryRepository {
+        fn default() -> Self {
+            Self { store: Arc::default(), soft_delete: false, allow_overwrite: false, clock: Arc::new(SystemClock) }
+        }
+    }
+
+    impl InMemoryRepository {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// `delete` marks records with `deleted_at` instead of removing them.
+        pub fn with_soft_delete() -> Self {
+            Self { soft_delete: true, ..Self::default() }
+        }
+
+        /// When set, `create` replaces an existing record instead of failing with `Conflict`.
+        pub fn allow_overwrite(mut self, allow: bool) -> Self {
+            self.allow_overwrite = allow;
+            self
+        }
+
+        /// Stamps soft deletes with `clock` instead of the system time.
+        pub fn with_clock(mut self, clock: impl Clock) -> Self {
+            self.clock = Arc::new(clock);
+            self
+        }
+
+        pub async fn ping(&self) -> Result<(), KernelError> {
+            Ok(())
+        }
+    }
+
+    pub struct InMemoryTransaction {
+        pending: Vec<Data>,
+        store: Arc<RwLock<HashMap<String, Data>>>,
+        allow_overwrite: bool
+    }
+
+    #[async_trait::async_trait]
+    impl Transaction for InMemoryTransaction {
+        async fn commit(self) -> Result<(), KernelError> {
+            let mut store = self.store.write().await;
+            if !self.allow_overwrite && self.pending.iter().any(|data| store.contains_key(data.id().as_ref())) {
+                return Err(KernelError::Conflict);
+            }
+            store.extend(self.pending.into_iter().map(|data| (data.id().to_string(), data)));
+            Ok(())
+        }
+
+        async fn rollback(self) -> Result<(), KernelError> {
+            Ok(())
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Repository for InMemoryRepository {
+        type Tx = InMemoryTransaction;
+
+        async fn begin(&self) -> Result<Self::Tx, KernelError> {
+            Ok(InMemoryTransaction { pending: Vec::new(), store: Arc::clone(&self.store), allow_overwrite: self.allow_overwrite })
+        }
+
+        async fn create(&self, data: &Data) -> Result<(), KernelError> {
+            let mut store = self.store.write().await;
+            if !self.allow_overwrite && store.contains_key(data.id().as_ref()) {
+                return Err(KernelError::Conflict);
+            }
+            store.insert(data.id().to_string(), data.clone());
+            Ok(())
+        }
+
+        /// The whole batch is inserted under one write lock.
+        async fn create_many_returning(&self, data: &[Data]) -> Result<Vec<Result<Data, KernelError>>, KernelError> {
+            let mut store = self.store.write().await;
+            Ok(data.iter().map(|data| {
+                if !self.allow_overwrite && store.contains_key(data.id().as_ref()) {
+                    return Err(KernelError::Conflict);
+                }
+                store.insert(data.id().to_string(), data.clone());
+                Ok(data.clone())
+            }).collect())
+        }
+
+        async fn create_in(&self, tx: &mut Self::Tx, data: &Data) -> Result<(), KernelError> {
+            tx.pending.push(data.clone());
+            Ok(())
+        }
+
+        async fn find_by_id(&self, id: &str) -> Result<Option<Data>, KernelError> {
+            Ok(self.store.read().await.get(id)
+                .filter(|data| !data.is_deleted())
+                .cloned())
+        }
+
+        async fn find_by_ids(&self, ids: &[String]) -> Result<Vec<Data>, KernelError> {
+            let store = self.store.read().await;
+            Ok(ids.iter()
+                .filter_map(|id| store.get(id))
+                .filter(|data| !data.is_deleted())
+                .cloned()
+                .collect())
+        }
+
+        async fn find_by_id_including_deleted(&self, id: &str) -> Result<Option<Data>, KernelError> {
+            Ok(self.store.read().await.get(id).cloned())
+        }
+
+        async fn update(&self, data: &Data, expected_version: u64) -> Result<bool, KernelError> {
+            Ok(self.update_returning(data, expected_version).await?.is_some())
+        }
+
+        async fn update_returning(&self, data: &Data, expected_version: u64) -> Result<Option<Data>, KernelError> {
+            match self.store.write().await.get_mut(data.id().as_ref()).filter(|data| !data.is_deleted()) {
+                Some(stored) if stored.version() != expected_version => Err(KernelError::Conflict),
+                Some(stored) => {
+                    *stored = stored.clone().with_contents_of(data).with_version(expected_version + 1);
+                    Ok(Some(stored.clone()))
+                },
+                None => Ok(None)
+            }
+        }
+
+        /// The patch is applied under the same write lock that looks the record up.
+        async fn update_fields(&self, id: &str, patch: DataPatch) -> Result<bool, KernelError> {
+            let mut store = self.store.write().await;
+            let Some(stored) = store.get_mut(id).filter(|data| !data.is_deleted()) else {
+                return Ok(false);
+            };
+            let mut next = stored.clone();
+            patch.apply(&mut next)?;
+            let version = next.version() + 1;
+            *stored = next.with_version(version);
+            Ok(true)
+        }
+
+        async fn delete(&self, id: &str) -> Result<bool, KernelError> {
+            let mut store = self.store.write().await;
+            match store.get_mut(id) {
+                Some(data) if data.is_deleted() => Ok(false),
+                Some(data) if self.soft_delete => {
+                    data.mark_deleted(&*self.clock);
+                    Ok(true)
+                },
+                Some(_) => Ok(store.remove(id).is_some()),
+                None => Ok(false)
+            }
+        }
+
+        /// Records are ordered by id so that pages are stable.
+        async fn list(&self, offset: u64, limit: u64) -> Result<Vec<Data>, KernelError> {
+            let store = self.store.read().await;
+            let mut data = store.values()
+                .filter(|data| !data.is_deleted())
+                .collect::<Vec<_>>();
+            data.sort_by(|a, b| a.id().as_ref().cmp(b.id().as_ref()));
+            Ok(data.into_iter()
+                .skip(offset as usize)
+                .take(limit as usize)
+                .cloned()
+                .collect())
+        }
+
+        async fn count(&self) -> Result<u64, KernelError> {
+            Ok(self.store.read().await.values()
+                .filter(|data| !data.is_deleted())
+                .count() as u64)
+        }
+
+        /// The store is snapshotted when the stream is first polled.
+        fn stream_all(&self) -> DataStream {
+            let store = Arc::clone(&self.store);
+            Box::pin(stream::once(async move {
+                let store = store.read().await;
+                let mut data = store.values()
+                    .filter(|data| !data.is_deleted())
+                    .cloned()
+                    .collect::<Vec<_>>();
+                data.sort_by(|a, b| a.id().as_ref().cmp(b.id().as_ref()));
+                stream::iter(data.into_iter().map(Ok))
+            }).flatten())
+        }
+
+        /// Atomic under the write lock. A live record is overwritten as by `update`, keeping its timestamps;
+        /// a soft-deleted one is left to `create`, which rejects it unless overwrites are allowed.
+        async fn upsert(&self, data: &Data) -> Result<bool, KernelError> {
+            let mut store = self.store.write().await;
+            match store.get_mut(data.id().as_ref()) {
+                Some(stored) if !stored.is_deleted() => {
+                    let version = stored.version() + 1;
+                    *stored = stored.clone().with_contents_of(data).with_version(version);
+                    Ok(false)
+                },
+                Some(_) if !self.allow_overwrite => Err(KernelError::Conflict),
+                _ => {
+                    store.insert(data.id().to_string(), data.clone());
+                    Ok(true)
+                }
+            }
+        }
+
+        async fn find_by_name_prefix(&self, prefix: &str, limit: u64) -> Result<Vec<Data>, KernelError> {
+            let store = self.store.read().await;
+            let mut data = store.values()
+                .filter(|data| !data.is_deleted() && data.name().as_ref().starts_with(prefix))
+                .collect::<Vec<_>>();
+            data.sort_by(|a, b| a.id().as_ref().cmp(b.id().as_ref()));
+            Ok(data.into_iter()
+                .take(limit as usize)
+                .cloned()
+                .collect())
+        }
+
+        async fn delete_many(&self, ids: &[String]) -> Result<Vec<String>, KernelError> {
+            let mut store = self.store.write().await;
+            let mut deleted = Vec::new();
+            for id in ids {
+                match store.get_mut(id) {
+                    Some(data) if data.is_deleted() => continue,
+                    Some(data) if self.soft_delete => data.mark_deleted(&*self.clock),
+                    Some(_) => {
+                        store.remove(id);
+                    },
+                    None => continue
+                }
+                deleted.push(id.clone());
+            }
+            Ok(deleted)
+        }
+    }
+
+    /// Fails any call on `R` that does not complete within the given duration.
+    #[derive(Clone)]
+    pub struct TimeoutRepository<R>(pub R, pub Duration);
+
+    impl<R> TimeoutRepository<R> {
+        async fn timed<T>(&self, fut: impl Future<Output = Result<T, KernelError>>) -> Result<T, KernelError> {
+            tokio::time::timeout(self.1, fut).await
+                .map_err(|_| KernelError::Timeout)?
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl<R: Repository> Repository for TimeoutRepository<R> {
+        type Tx = R::Tx;
+
+        async fn begin(&self) -> Result<Self::Tx, KernelError> {
+            self.timed(self.0.begin()).await
+        }
+
+        async fn create(&self, data: &Data) -> Result<(), KernelError> {
+            self.timed(self.0.create(data)).await
+        }
+
+        async fn create_in(&self, tx: &mut Self::Tx, data: &Data) -> Result<(), KernelError> {
+            self.timed(self.0.create_in(tx, data)).await
+        }
+
+        async fn create_many(&self, data: &[Data]) -> Result<(), KernelError> {
+            self.timed(self.0.create_many(data)).await
+        }
+
+        async fn create_many_returning(&self, data: &[Data]) -> Result<Vec<Result<Data, KernelError>>, KernelError> {
+            self.timed(self.0.create_many_returning(data)).await
+        }
+
+        async fn find_by_id(&self, id: &str) -> Result<Option<Data>, KernelError> {
+            self.timed(self.0.find_by_id(id)).await
+        }
+
+        async fn find_by_id_including_deleted(&self, id: &str) -> Result<Option<Data>, KernelError> {
+            self.timed(self.0.find_by_id_including_deleted(id)).await
+        }
+
+        async fn exists(&self, id: &str) -> Result<bool, KernelError> {
+            self.timed(self.0.exists(id)).await
+        }
+
+        async fn update(&self, data: &Data, expected_version: u64) -> Result<bool, KernelError> {
+            self.timed(self.0.update(data, expected_version)).await
+        }
+
+        async fn update_returning(&self, data: &Data, expected_version: u64) -> Result<Option<Data>, KernelError> {
+            self.timed(self.0.update_returning(data, expected_version)).await
+        }
+
+        async fn delete(&self, id: &str) -> Result<bool, KernelError> {
+            self.timed(self.0.delete(id)).await
+        }
+
+        async fn list(&self, offset: u64, limit: u64) -> Result<Vec<Data>, KernelError> {
+            self.timed(self.0.list(offset, limit)).await
+        }
+
+        async fn count(&self) -> Result<u64, KernelError> {
+            self.timed(self.0.count()).await
+        }
+
+        fn stream_all(&self) -> DataStream {
+            self.0.stream_all()
+        }
+
+        async fn upsert(&self, data: &Data) -> Result<bool, KernelError> {
+            self.timed(self.0.upsert(data)).await
+        }
+
+        async fn find_by_name_prefix(&self, prefix: &str, limit: u64) -> Result<Vec<Data>, KernelError> {
+            self.timed(self.0.find_by_name_prefix(prefix, limit)).await
+        }
+
+        async fn delete_many(&self, ids: &[String]) -> Result<Vec<String>, KernelError> {
+            self.timed(self.0.delete_many(ids)).await
+        }
+
+        async fn update_fields(&self, id: &str, patch: DataPatch) -> Result<bool, KernelError> {
+            self.timed(self.0.update_fields(id, patch)).await
+        }
+
+        async fn find_by_ids(&self, ids: &[String]) -> Result<Vec<Data>, KernelError> {
+            self.timed(self.0.find_by_ids(ids)).await
+        }
+
+        async fn create_returning(&self, data: &Data) -> Result<Data, KernelError> {
+            self.timed(self.0.create_returning(data)).await
+        }
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct RetryPolicy {
+        pub max_retries: u32,
+        pub base_delay: Duration,
+    }
+
+    /// Retries `create` and `create_returning` on `KernelError::Driver`, doubling the delay after each attempt.
+    ///
+    /// Other errors are returned immediately, and all other calls are passed through as-is.
+    #[derive(Clone)]
+    pub struct RetryRepository<R>(pub R, pub RetryPolicy);
+
+    impl<R> RetryRepository<R> {
+        async fn retry<T, Fut>(&self, op: impl Fn() -> Fut) -> Result<T, KernelError>
+            where Fut: Future<Output = Result<T, KernelError>>
+        {
+            let mut delay = self.1.base_delay;
+            let mut retries = 0;
+            loop {
+                match op().await {
+                    Err(KernelError::Driver(_)) if retries < self.1.max_retries => {
+                        tokio::time::sleep(delay).await;
+                        delay *= 2;
+                        retries += 1;
+                    },
+                    res => return res
+                }
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl<R: Repository> Repository for RetryRepository<R> {
+        type Tx = R::Tx;
+
+        async fn begin(&self) -> Result<Self::Tx, KernelError> {
+            self.0.begin().await
+        }
+
+        async fn create(&self, data: &Data) -> Result<(), KernelError> {
+            self.retry(|| self.0.create(data)).await
+        }
+
+        async fn create_returning(&self, data: &Data) -> Result<Data, KernelError> {
+            self.retry(|| self.0.create_returning(data)).await
+        }
+
+        async fn create_in(&self, tx: &mut Self::Tx, data: &Data) -> Result<(), KernelError> {
+            self.0.create_in(tx, data).await
+        }
+
+        async fn create_many(&self, data: &[Data]) -> Result<(), KernelError> {
+            self.0.create_many(data).await
+        }
+
+        async fn create_many_returning(&self, data: &[Data]) -> Result<Vec<Result<Data, KernelError>>, KernelError> {
+            self.0.create_many_returning(data).await
+        }
+
+        async fn find_by_id(&self, id: &str) -> Result<Option<Data>, KernelError> {
+            self.0.find_by_id(id).await
+        }
+
+        async fn find_by_id_including_deleted(&self, id: &str) -> Result<Option<Data>, KernelError> {
+            self.0.find_by_id_including_deleted(id).await
+        }
+
+        async fn exists(&self, id: &str) -> Result<bool, KernelError> {
+            self.0.exists(id).await
+        }
+
+        async fn update(&self, data: &Data, expected_version: u64) -> Result<bool, KernelError> {
+            self.0.update(data, expected_version).await
+        }
+
+        async fn update_returning(&self, data: &Data, expected_version: u64) -> Result<Option<Data>, KernelError> {
+            self.0.update_returning(data, expected_version).await
+        }
+
+        async fn delete(&self, id: &str) -> Result<bool, KernelError> {
+            self.0.delete(id).await
+        }
+
+        async fn list(&self, offset: u64, limit: u64) -> Result<Vec<Data>, KernelError> {
+            self.0.list(offset, limit).await
+        }
+
+        async fn count(&self) -> Result<u64, KernelError> {
+            self.0.count().await
+        }
+
+        fn stream_all(&self) -> DataStream {
+            self.0.stream_all()
+        }
+
+        async fn upsert(&self, data: &Data) -> Result<bool, KernelError> {
+            self.0.upsert(data).await
+        }
+
+        async fn find_by_name_prefix(&self, prefix: &str, limit: u64) -> Result<Vec<Data>, KernelError> {
+            self.0.find_by_name_prefix(prefix, limit).await
+        }
+
+        async fn delete_many(&self, ids: &[String]) -> Result<Vec<String>, KernelError> {
+            self.0.delete_many(ids).await
+        }
+
+        async fn update_fields(&self, id: &str, patch: DataPatch) -> Result<bool, KernelError> {
+            self.0.update_fields(id, patch).await
+        }
+
+        async fn find_by_ids(&self, ids: &[String]) -> Result<Vec<Data>, KernelError> {
+            self.0.find_by_ids(ids).await
+        }
+    }
+
+    /// What `DualWriteRepository` does when the primary write succeeds but the secondary fails.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub enum DualWritePolicy {
+        /// Return the secondary's error. The primary write is not undone.
+        #[default]
+        FailFast,
+        /// Log the secondary's error and report the primary's result.
+        BestEffort,
+    }
+
+    /// Writes go to `primary` and then `secondary`; reads are served from `primary` only.
+    ///
+    /// Meant for migrating between stores. A failed primary write is never mirrored.
+    pub struct DualWriteRepository<A, B> {
+        primary: A,
+        secondary: B,
+        policy: DualWritePolicy,
+        sink: Arc<dyn LogSink>,
+    }
+
+    impl<A, B> DualWriteRepository<A, B> {
+        pub fn new(primary: A, secondary: B, policy: DualWritePolicy) -> Self {
+            Self { primary, secondary, policy, sink: default_sink() }
+        }
+
+        pub fn primary(&self) -> &A {
+            &self.primary
+        }
+
+        pub fn secondary(&self) -> &B {
+            &self.secondary
+        }
+
+        /// Where secondary failures tolerated under `BestEffort` are reported.
+        pub fn with_sink(mut self, sink: impl LogSink) -> Self {
+            self.sink = Arc::new(sink);
+            self
+        }
+
+        async fn mirror<T, U>(
+            &self,
+            primary: Result<T, KernelError>,
+            secondary: impl Future<Output = Result<U, KernelError>>
+        ) -> Result<T, KernelError> {
+            let value = primary?;
+            mirror_secondary(self.policy, &*self.sink, value, secondary.await)
+        }
+    }
+
+    fn mirror_secondary<T, U>(
+        policy: DualWritePolicy,
+        sink: &dyn LogSink,
+        value: T,
+        secondary: Result<U, KernelError>
+    ) -> Result<T, KernelError> {
+        match (secondary, policy) {
+            (Ok(_), _) => Ok(value),
+            (Err(e), DualWritePolicy::FailFast) => Err(e),
+            (Err(e), DualWritePolicy::BestEffort) => {
+                sink.log(&format!("secondary write failed: {}", e));
+                Ok(value)
+            }
+        }
+    }
+
+    pub struct DualTransaction<A, B> {
+        primary: A,
+        secondary: B,
+        policy: DualWritePolicy,
+        sink: Arc<dyn LogSink>,
+    }
+
+    #[async_trait::async_trait]
+    impl<A: Transaction, B: Transaction> Transaction for DualTransaction<A, B> {
+        async fn commit(self) -> Result<(), KernelError> {
+            self.primary.commit().await?;
+            mirror_secondary(self.policy, &*self.sink, (), self.secondary.commit().await)
+        }
+
+        async fn rollback(self) -> Result<(), KernelError> {
+            let primary = self.primary.rollback().await;
+            let secondary = self.secondary.rollback().await;
+            primary.and(secondary)
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl<A: Repository, B: Repository> Repository for DualWriteRepository<A, B> {
+        type Tx = DualTransaction<A::Tx, B::Tx>;
+
+        /// If the secondary cannot begin, the primary transaction is rolled back before the error is returned.
+        async fn begin(&self) -> Result<Self::Tx, KernelError> {
+            let primary = self.primary.begin().await?;
+            let secondary = match self.secondary.begin().await {
+                Ok(secondary) => secondary,
+                Err(e) => {
+                    if let Err(rollback) = primary.rollback().await {
+                        self.sink.log(&format!("primary rollback failed: {}", rollback));
+                    }
+                    return Err(e);
+                }
+            };
+            Ok(DualTransaction { primary, secondary, policy: self.policy, sink: Arc::clone(&self.sink) })
+        }
+
+        async fn create(&self, data: &Data) -> Result<(), KernelError> {
+            self.mirror(self.primary.create(data).await, self.secondary.create(data)).await
+        }
+
+        async fn create_in(&self, tx: &mut Self::Tx, data: &Data) -> Result<(), KernelError> {
+            self.mirror(self.primary.create_in(&mut tx.primary, data).await, self.secondary.create_in(&mut tx.secondary, data)).await
+        }
+
+        async fn create_many(&self, data: &[Data]) -> Result<(), KernelError> {
+            self.mirror(self.primary.create_many(data).await, self.secondary.create_many(data)).await
+        }
+
+        /// Only the rows the primary stored are mirrored.
+        async fn create_many_returning(&self, data: &[Data]) -> Result<Vec<Result<Data, KernelError>>, KernelError> {
+            let results = self.primary.create_many_returning(data).await?;
+            let stored = results.iter().filter_map(|res| res.as_ref().ok().cloned()).collect::<Vec<_>>();
+            mirror_secondary(self.policy, &*self.sink, results, self.secondary.create_many(&stored).await)
+        }
+
+        async fn find_by_id(&self, id: &str) -> Result<Option<Data>, KernelError> {
+            self.primary.find_by_id(id).await
+        }
+
+        async fn find_by_id_including_deleted(&self, id: &str) -> Result<Option<Data>, KernelError> {
+            self.primary.find_by_id_including_deleted(id).await
+        }
+
+        async fn exists(&self, id: &str) -> Result<bool, KernelError> {
+            self.primary.exists(id).await
+        }
+
+        async fn update(&self, data: &Data, expected_version: u64) -> Result<bool, KernelError> {
+            self.mirror(self.primary.update(data, expected_version).await, self.secondary.update(data, expected_version)).await
+        }
+
+        async fn update_returning(&self, data: &Data, expected_version: u64) -> Result<Option<Data>, KernelError> {
+            self.mirror(self.primary.update_returning(data, expected_version).await, self.secondary.update(data, expected_version)).await
+        }
+
+        async fn upsert(&self, data: &Data) -> Result<bool, KernelError> {
+            self.mirror(self.primary.upsert(data).await, self.secondary.upsert(data)).await
+        }
+
+        async fn delete(&self, id: &str) -> Result<bool, KernelError> {
+            self.mirror(self.primary.delete(id).await, self.secondary.delete(id)).await
+        }
+
+        async fn delete_many(&self, ids: &[String]) -> Result<Vec<String>, KernelError> {
+            self.mirror(self.primary.delete_many(ids).await, self.secondary.delete_many(ids)).await
+        }
+
+        async fn list(&self, offset: u64, limit: u64) -> Result<Vec<Data>, KernelError> {
+            self.primary.list(offset, limit).await
+        }
+
+        async fn count(&self) -> Result<u64, KernelError> {
+            self.primary.count().await
+        }
+
+        async fn find_by_name_prefix(&self, prefix: &str, limit: u64) -> Result<Vec<Data>, KernelError> {
+            self.primary.find_by_name_prefix(prefix, limit).await
+        }
+
+        fn stream_all(&self) -> DataStream {
+            self.primary.stream_all()
+        }
+
+        async fn update_fields(&self, id: &str, patch: DataPatch) -> Result<bool, KernelError> {
+            self.mirror(self.primary.update_fields(id, patch.clone()).await, self.secondary.update_fields(id, patch)).await
+        }
+
+        async fn find_by_ids(&self, ids: &[String]) -> Result<Vec<Data>, KernelError> {
+            self.primary.find_by_ids(ids).await
+        }
+
+        /// The secondary is sent the record as the primary stored it.
+        async fn create_returning(&self, data: &Data) -> Result<Data, KernelError> {
+            let stored = self.primary.create_returning(data).await?;
+            let secondary = self.secondary.create(&stored).await;
+            mirror_secondary(self.policy, &*self.sink, stored, secondary)
+        }
+    }
+
+    /// Bounded least-recently-used map; touching an entry moves it to the back of `order`.
+    ///
+    /// `generation` counts invalidations, so a read that raced one can tell its value may be stale.
+    struct Lru {
+        capacity: usize,
+        entries: HashMap<String, Data>,
+        order: VecDeque<String>,
+        generation: u64,
+    }
+
+    impl Lru {
+        fn get(&mut self, id: &str) -> Option<Data> {
+            let data = self.entries.get(id)?.clone();
+            self.touch(id);
+            Some(data)
+        }
+
+        fn put(&mut self, data: Data) {
+            if self.capacity == 0 {
+                return;
+            }
+            let id = data.id().to_string();
+            if self.entries.insert(id.clone(), data).is_some() {
+                self.touch(&id);
+                return;
+            }
+            self.order.push_back(id);
+            if self.order.len() > self.capacity {
+                if let Some(evicted) = self.order.pop_front() {
+                    self.entries.remove(&evicted);
+                }
+            }
+        }
+
+        /// Bumps `generation` even if `id` is not cached, since a read of it may be in flight.
+        fn remove(&mut self, id: &str) {
+            self.generation += 1;
+            if self.entries.remove(id).is_some() {
+                self.order.retain(|key| key != id);
+            }
+        }
+
+        fn touch(&mut self, id: &str) {
+            if let Some(pos) = self.order.iter().position(|key| key == id) {
+                let key = self.order.remove(pos).expect("position is in bounds");
+                self.order.push_back(key);
+            }
+        }
+    }
+
+    /// Read-through cache for `find_by_id`, holding at most `capacity` records.
+    ///
+    /// Writes through this wrapper evict the affected ids; writes that bypass it are not seen.
+    pub struct CachingRepository<R> {
+        inner: R,
+        cache: Mutex<Lru>,
+    }
+
+    impl<R> CachingRepository<R> {
+        pub fn new(inner: R, capacity: usize) -> Self {
+            let cache = Lru { capacity, entries: HashMap::new(), order: VecDeque::new(), generation: 0 };
+            Self { inner, cache: Mutex::new(cache) }
+        }
+
+        pub fn inner(&self) -> &R {
+            &self.inner
+        }
+
+        fn invalidate(&self, id: &str) {
+            self.cache.lock().expect("cache lock poisoned").remove(id);
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl<R: Repository> Repository for CachingRepository<R> {
+        type Tx = R::Tx;
+
+        async fn begin(&self) -> Result<Self::Tx, KernelError> {
+            self.inner.begin().await
+        }
+
+        async fn create(&self, data: &Data) -> Result<(), KernelError> {
+            let res = self.inner.create(data).await;
+            self.invalidate(data.id().as_ref());
+            res
+        }
+
+        async fn create_in(&self, tx: &mut Self::Tx, data: &Data) -> Result<(), KernelError> {
+            let res = self.inner.create_in(tx, data).await;
+            self.invalidate(data.id().as_ref());
+            res
+        }
+
+        async fn create_many(&self, data: &[Data]) -> Result<(), KernelError> {
+            let res = self.inner.create_many(data).await;
+            data.iter().for_each(|data| self.invalidate(data.id().as_ref()));
+            res
+        }
+
+        async fn create_many_returning(&self, data: &[Data]) -> Result<Vec<Result<Data, KernelError>>, KernelError> {
+            let res = self.inner.create_many_returning(data).await;
+            data.iter().for_each(|data| self.invalidate(data.id().as_ref()));
+            res
+        }
+
+        /// A miss is only cached if no write was invalidated while `R` was being read.
+        async fn find_by_id(&self, id: &str) -> Result<Option<Data>, KernelError> {
+            let generation = {
+                let mut cache = self.cache.lock().expect("cache lock poisoned");
+                if let Some(hit) = cache.get(id) {
+                    return Ok(Some(hit));
+                }
+                cache.generation
+            };
+            let found = self.inner.find_by_id(id).await?;
+            if let Some(data) = &found {
+                let mut cache = self.cache.lock().expect("cache lock poisoned");
+                if cache.generation == generation {
+                    cache.put(data.clone());
+                }
+            }
+            Ok(found)
+        }
+
+        /// Cache hits are served directly; only the misses go to `R`, in one batch.
+        async fn find_by_ids(&self, ids: &[String]) -> Result<Vec<Data>, KernelError> {
+            let (mut found, misses, generation) = {
+                let mut cache = self.cache.lock().expect("cache lock poisoned");
+                let mut found = Vec::new();
+                let mut misses = Vec::new();
+                for id in ids {
+                    match cache.get(id) {
+                        Some(hit) => found.push(hit),
+                        None => misses.push(id.clone())
+                    }
+                }
+                (found, misses, cache.generation)
+            };
+            if misses.is_empty() {
+                return Ok(found);
+            }
+            let fetched = self.inner.find_by_ids(&misses).await?;
+            let mut cache = self.cache.lock().expect("cache lock poisoned");
+            if cache.generation == generation {
+                fetched.iter().for_each(|data| cache.put(data.clone()));
+            }
+            found.extend(fetched);
+            Ok(found)
+        }
+
+        async fn find_by_id_including_deleted(&self, id: &str) -> Result<Option<Data>, KernelError> {
+            self.inner.find_by_id_including_deleted(id).await
+        }
+
+        async fn exists(&self, id: &str) -> Result<bool, KernelError> {
+            Ok(self.find_by_id(id).await?.is_some())
+        }
+
+        async fn update(&self, data: &Data, expected_version: u64) -> Result<bool, KernelError> {
+            let res = self.inner.update(data, expected_version).await;
+            self.invalidate(data.id().as_ref());
+            res
+        }
+
+        async fn update_returning(&self, data: &Data, expected_version: u64) -> Result<Option<Data>, KernelError> {
+            let res = self.inner.update_returning(data, expected_version).await;
+            self.invalidate(data.id().as_ref());
+            res
+        }
+
+        async fn delete(&self, id: &str) -> Result<bool, KernelError> {
+            let res = self.inner.delete(id).await;
+            self.invalidate(id);
+            res
+        }
+
+        async fn list(&self, offset: u64, limit: u64) -> Result<Vec<Data>, KernelError> {
+            self.inner.list(offset, limit).await
+        }
+
+        async fn count(&self) -> Result<u64, KernelError> {
+            self.inner.count().await
+        }
+
+        fn stream_all(&self) -> DataStream {
+            self.inner.stream_all()
+        }
+
+        async fn upsert(&self, data: &Data) -> Result<bool, KernelError> {
+            let res = self.inner.upsert(data).await;
+            self.invalidate(data.id().as_ref());
+            res
+        }
+
+        async fn find_by_name_prefix(&self, prefix: &str, limit: u64) -> Result<Vec<Data>, KernelError> {
+            self.inner.find_by_name_prefix(prefix, limit).await
+        }
+
+        async fn delete_many(&self, ids: &[String]) -> Result<Vec<String>, KernelError> {
+            let res = self.inner.delete_many(ids).await;
+            ids.iter().for_each(|id| self.invalidate(id));
+            res
+        }
+
+        async fn update_fields(&self, id: &str, patch: DataPatch) -> Result<bool, KernelError> {
+            let res = self.inner.update_fields(id, patch).await;
+            self.invalidate(id);
+            res
+        }
+
+        async fn create_returning(&self, data: &Data) -> Result<Data, KernelError> {
+            let res = self.inner.create_returning(data).await;
+            self.invalidate(data.id().as_ref());
+            res
+        }
+    }
+
+    /// Records `repository_operation_duration_seconds{op}` for every call on `R`.
+    ///
+    /// `stream_all` is passed through untimed, since the stream outlives the call.
+    #[cfg(feature = "metrics")]
+    pub struct InstrumentedRepository<R> {
+        inner: R,
+    }
+
+    #[cfg(feature = "metrics")]
+    impl<R> InstrumentedRepository<R> {
+        pub fn new(inner: R) -> Self {
+            Self { inner }
+        }
+
+        async fn timed<T>(&self, op: &'static str, fut: impl Future<Output = T>) -> T {
+            let start = std::time::Instant::now();
+            let output = fut.await;
+            metrics::histogram!("repository_operation_duration_seconds", "op" => op).record(start.elapsed().as_secs_f64());
+            output
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    #[async_trait::async_trait]
+    impl<R: Repository> Repository for InstrumentedRepository<R> {
+        type Tx = R::Tx;
+
+        async fn begin(&self) -> Result<Self::Tx, KernelError> {
+            self.timed("begin", self.inner.begin()).await
+        }
+
+        async fn create(&self, data: &Data) -> Result<(), KernelError> {
+            self.timed("create", self.inner.create(data)).await
+        }
+
+        async fn create_in(&self, tx: &mut Self::Tx, data: &Data) -> Result<(), KernelError> {
+            self.timed("create_in", self.inner.create_in(tx, data)).await
+        }
+
+        async fn create_many(&self, data: &[Data]) -> Result<(), KernelError> {
+            self.timed("create_many", self.inner.create_many(data)).await
+        }
+
+        async fn create_many_returning(&self, data: &[Data]) -> Result<Vec<Result<Data, KernelError>>, KernelError> {
+            self.timed("create_many_returning", self.inner.create_many_returning(data)).await
+        }
+
+        async fn find_by_id(&self, id: &str) -> Result<Option<Data>, KernelError> {
+            self.timed("find_by_id", self.inner.find_by_id(id)).await
+        }
+
+        async fn find_by_id_including_deleted(&self, id: &str) -> Result<Option<Data>, KernelError> {
+            self.timed("find_by_id_including_deleted", self.inner.find_by_id_including_deleted(id)).await
+        }
+
+        async fn exists(&self, id: &str) -> Result<bool, KernelError> {
+            self.timed("exists", self.inner.exists(id)).await
+        }
+
+        async fn update(&self, data: &Data, expected_version: u64) -> Result<bool, KernelError> {
+            self.timed("update", self.inner.update(data, expected_version)).await
+        }
+
+        async fn update_returning(&self, data: &Data, expected_version: u64) -> Result<Option<Data>, KernelError> {
+            self.timed("update_returning", self.inner.update_returning(data, expected_version)).await
+        }
+
+        async fn delete(&self, id: &str) -> Result<bool, KernelError> {
+            self.timed("delete", self.inner.delete(id)).await
+        }
+
+        async fn list(&self, offset: u64, limit: u64) -> Result<Vec<Data>, KernelError> {
+            self.timed("list", self.inner.list(offset, limit)).await
+        }
+
+        async fn count(&self) -> Result<u64, KernelError> {
+            self.timed("count", self.inner.count()).await
+        }
+
+        fn stream_all(&self) -> DataStream {
+            self.inner.stream_all()
+        }
+
+        async fn upsert(&self, data: &Data) -> Result<bool, KernelError> {
+            self.timed("upsert", self.inner.upsert(data)).await
+        }
+
+        async fn find_by_name_prefix(&self, prefix: &str, limit: u64) -> Result<Vec<Data>, KernelError> {
+            self.timed("find_by_name_prefix", self.inner.find_by_name_prefix(prefix, limit)).await
+        }
+
+        async fn delete_many(&self, ids: &[String]) -> Result<Vec<String>, KernelError> {
+            self.timed("delete_many", self.inner.delete_many(ids)).await
+        }
+
+        async fn update_fields(&self, id: &str, patch: DataPatch) -> Result<bool, KernelError> {
+            self.timed("update_fields", self.inner.update_fields(id, patch)).await
+        }
+
+        async fn find_by_ids(&self, ids: &[String]) -> Result<Vec<Data>, KernelError> {
+            self.timed("find_by_ids", self.inner.find_by_ids(ids)).await
+        }
+
+        async fn create_returning(&self, data: &Data) -> Result<Data, KernelError> {
+            self.timed("create_returning", self.inner.create_returning(data)).await
+        }
+    }
+}
+
+/// A.k.a Domain Layer
+pub mod kernel {
+    pub mod error {
+        pub use controller_test::error::KernelError;
+    }
+
+    /// Numeric codes for outcomes, chosen to line up with HTTP status codes.
+    pub mod codes {
+        /// The operation succeeded.
+        pub const OK: u16 = 200;
+        /// The input was rejected before reaching the repository.
+        pub const ERR_VALIDATION: u16 = 400;
+        /// No record exists for the given id.
+        pub const ERR_NOT_FOUND: u16 = 404;
+        /// The record already exists or was modified concurrently.
+        pub const ERR_CONFLICT: u16 = 409;
+        /// The request carried more items than the operation accepts.
+        pub const ERR_TOO_LARGE: u16 = 413;
+        /// The caller went away before the operation finished; nginx's "client closed request".
+        pub const ERR_CANCELLED: u16 = 499;
+        /// The driver failed or timed out; details are not exposed.
+        pub const ERR_INTERNAL: u16 = 500;
+        /// The pool is shutting down and no longer accepts work.
+        pub const ERR_UNAVAILABLE: u16 = 503;
+    }
+
+    use std::{
+        future::Future,
+        panic::{self, AssertUnwindSafe},
+        pin::Pin,
+        sync::{atomic::{AtomicU64, Ordering}, Arc},
+    };
+
+    use futures::{FutureExt, Stream};
+
+    use self::error::KernelError;
+
+    pub use controller_test::{Clock, Data, DataId, DataName, DestructData, FixedClock, SystemClock};
+
+    /// Mints ids on the server side, for records the client does not name.
+    pub trait IdGenerator: 'static + Send + Sync {
+        fn generate(&self) -> DataId;
+    }
+
+    /// Random v4 UUIDs in hyphenated form.
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct UuidGenerator;
+
+    impl IdGenerator for UuidGenerator {
+        fn generate(&self) -> DataId {
+            DataId::new(uuid::Uuid::new_v4().to_string()).expect("a uuid is a valid id")
+        }
+    }
+
+    /// Yields `{prefix}1`, `{prefix}2`, ... in order, for deterministic tests.
+    #[derive(Debug)]
+    pub struct SequenceGenerator {
+        prefix: String,
+        next: AtomicU64
+    }
+
+    impl SequenceGenerator {
+        pub fn new(prefix: impl Into<String>) -> Self {
+            Self { prefix: prefix.into(), next: AtomicU64::new(1) }
+        }
+    }
+
+    impl IdGenerator for SequenceGenerator {
+        fn generate(&self) -> DataId {
+            let n = self.next.fetch_add(1, Ordering::Relaxed);
+            DataId::new(format!("{}{}", self.prefix, n)).expect("prefix leaves room for the counter")
+        }
+    }
+
+    depend_on!(trait DependOnIdGenerator => IdGenerator, id_generator);
+
+    depend_on!(trait DependOnClock => Clock, clock);
+
+    /// Partial update of a record; `None` fields are left untouched.
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    #[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+    pub struct DataPatch {
+        pub name: Option<String>
+    }
+
+    impl DataPatch {
+        pub fn is_empty(&self) -> bool {
+            self.name.is_none()
+        }
+
+        /// Leaves `data` as it was if any field is rejected. The version is not touched.
+        pub fn apply(self, data: &mut Data) -> Result<(), KernelError> {
+            let DataPatch { name } = self;
+            if let Some(name) = name {
+                data.set_name(name)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    pub enum DomainEvent {
+        DataCreated { id: DataId },
+    }
+
+    /// Per-request data carried from the adaptor down to the driver without extra parameters.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct RequestContext {
+        pub request_id: String,
+        pub tenant_id: Option<String>,
+    }
+
+    tokio::task_local! {
+        static REQUEST_CONTEXT: RequestContext;
+    }
+
+    impl RequestContext {
+        pub fn new(request_id: impl Into<String>) -> Self {
+            Self { request_id: request_id.into(), tenant_id: None }
+        }
+
+        pub fn with_tenant(mut self, tenant_id: impl Into<String>) -> Self {
+            self.tenant_id = Some(tenant_id.into());
+            self
+        }
+
+        /// Makes `self` visible to `current` for the duration of `fut`.
+        pub async fn scope<F: Future>(self, fut: F) -> F::Output {
+            REQUEST_CONTEXT.scope(self, fut).await
+        }
+
+        /// The context of the enclosing `scope`, if any.
+        pub fn current() -> Option<Self> {
+            REQUEST_CONTEXT.try_with(Clone::clone).ok()
+        }
+    }
+
+    #[async_trait::async_trait]
+    pub trait EventPublisher: 'static + Send + Sync {
+        async fn publish(&self, event: DomainEvent);
+    }
+
+    /// Discards every event.
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct NoopEventPublisher;
+
+    #[async_trait::async_trait]
+    impl EventPublisher for NoopEventPublisher {
+        async fn publish(&self, _event: DomainEvent) {}
+    }
+
+    depend_on!(trait DependOnEventPublisher => EventPublisher, event_publisher);
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum AuditAction {
+        Created,
+        Updated,
+        Deleted,
+    }
+
+    /// Audit trail of successful writes; failed operations are never recorded.
+    pub trait AuditLog: 'static + Send + Sync {
+        fn record(&self, action: AuditAction, id: &str);
+    }
+
+    /// Discards every entry.
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct NoopAuditLog;
+
+    impl AuditLog for NoopAuditLog {
+        fn record(&self, _action: AuditAction, _id: &str) {}
+    }
+
+    depend_on!(trait DependOnAuditLog => AuditLog, audit_log);
+
+    pub type DataStream = Pin<Box<dyn Stream<Item = Result<Data, KernelError>> + Send>>;
+
+    /// The future a `Repository::transaction` body returns; it may borrow the transaction.
+    pub type TxFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, KernelError>> + Send + 'a>>;
+
+    #[async_trait::async_trait]
+    pub trait Transaction: 'static + Send {
+        async fn commit(self) -> Result<(), KernelError>;
+        async fn rollback(self) -> Result<(), KernelError>;
+    }
+
+    #[async_trait::async_trait]
+    pub trait Repository: 'static + Send + Sync {
+        type Tx: Transaction;
+
+        async fn begin(&self) -> Result<Self::Tx, KernelError>;
+        async fn create(&self, data: &Data) -> Result<(), KernelError>;
+        /// Like `create`, returning the record as stored, including any fields the backend assigned.
+        ///
+        /// Drivers that stamp fields on insert should override this; the default returns `data` as given.
+        async fn create_returning(&self, data: &Data) -> Result<Data, KernelError> {
+            self.create(data).await?;
+            Ok(data.clone())
+        }
+        /// Writes are only visible once `tx` is committed.
+        async fn create_in(&self, tx: &mut Self::Tx, data: &Data) -> Result<(), KernelError>;
+
+        /// Runs `f` in a fresh transaction, committing on `Ok` and rolling back on `Err` or panic.
+        ///
+        /// `f` is handed the repository back so that its future only borrows what it is given;
+        /// anything else it needs should be moved in. A panic is resumed once the rollback has been attempted.
+        async fn transaction<F, T>(&self, f: F) -> Result<T, KernelError>
+            where F: for<'t> FnOnce(&'t Self, &'t mut Self::Tx) -> TxFuture<'t, T> + Send,
+                  T: Send
+        {
+            let mut tx = self.begin().await?;
+            match AssertUnwindSafe(f(self, &mut tx)).catch_unwind().await {
+                Ok(Ok(value)) => {
+                    tx.commit().await?;
+                    Ok(value)
+                },
+                Ok(Err(e)) => {
+                    tx.rollback().await?;
+                    Err(e)
+                },
+                Err(panic) => {
+                    let _ = tx.rollback().await;
+                    panic::resume_unwind(panic)
+                }
+            }
+        }
+
+        /// Drivers capable of bulk inserts should override this.
+        async fn create_many(&self, data: &[Data]) -> Result<(), KernelError> {
+            for data in data {
+                self.create(data).await?;
+            }
+            Ok(())
+        }
+        /// Like `create_many`, reporting each row as `create_returning` would, in input order.
+        ///
+        /// A conflicting row only fails itself; `Err` is for failures of the batch as a whole.
+        /// Drivers capable of bulk inserts should override this.
+        async fn create_many_returning(&self, data: &[Data]) -> Result<Vec<Result<Data, KernelError>>, KernelError> {
+            let mut results = Vec::with_capacity(data.len());
+            for data in data {
+                results.push(self.create_returning(data).await);
+            }
+            Ok(results)
+        }
+        /// Soft-deleted records are not returned.
+        async fn find_by_id(&self, id: &str) -> Result<Option<Data>, KernelError>;
+
+        /// Only the live records among `ids`, in no particular order; unknown ids are skipped.
+        ///
+        /// Drivers capable of batched reads should override this.
+        async fn find_by_ids(&self, ids: &[String]) -> Result<Vec<Data>, KernelError> {
+            let mut found = Vec::with_capacity(ids.len());
+            for id in ids {
+                found.extend(self.find_by_id(id).await?);
+            }
+            Ok(found)
+        }
+
+        /// For admin paths. Drivers without soft deletes need not override this.
+        async fn find_by_id_including_deleted(&self, id: &str) -> Result<Option<Data>, KernelError> {
+            self.find_by_id(id).await
+        }
+
+        /// Drivers that can check existence more cheaply should override this.
+        async fn exists(&self, id: &str) -> Result<bool, KernelError> {
+            Ok(self.find_by_id(id).await?.is_some())
+        }
+        /// Fails with `KernelError::Conflict` unless the stored version is `expected_version`,
+        /// and otherwise stores the name and attributes of `data` under the next version.
+        /// The stored `created_at` and `deleted_at` are kept.
+        ///
+        /// Returns `false` if there is no live record with that id.
+        async fn update(&self, data: &Data, expected_version: u64) -> Result<bool, KernelError>;
+        /// Like `update`, returning the record as stored afterwards, or `None` if there is no live record with that id.
+        ///
+        /// The default re-reads the record; drivers that can return it from the write should override this.
+        async fn update_returning(&self, data: &Data, expected_version: u64) -> Result<Option<Data>, KernelError> {
+            if !self.update(data, expected_version).await? {
+                return Ok(None);
+            }
+            self.find_by_id(data.id().as_ref()).await
+        }
+
+        /// Returns `true` if `data` was inserted and `false` if it replaced an existing record.
+        ///
+        /// The default is not atomic; drivers should override it when they can.
+        async fn upsert(&self, data: &Data) -> Result<bool, KernelError> {
+            if let Some(stored) = self.find_by_id(data.id().as_ref()).await? {
+                if self.update(data, stored.version()).await? {
+                    return Ok(false);
+                }
+            }
+            self.create(data).await?;
+            Ok(true)
+        }
+        /// Applies only the `Some` fields of `patch` and bumps the version, without a version check.
+        ///
+        /// Returns `false` if there is no live record with that id. The default is a read-modify-write
+        /// and not atomic; drivers should override it when they can.
+        async fn update_fields(&self, id: &str, patch: DataPatch) -> Result<bool, KernelError> {
+            let Some(mut data) = self.find_by_id(id).await? else {
+                return Ok(false);
+            };
+            let version = data.version();
+            patch.apply(&mut data)?;
+            self.update(&data, version).await
+        }
+        /// Returns `true` if a live record was deleted. Deleting an id that does not exist is not an error
+        /// and returns `false`.
+        ///
+        /// Drivers may implement this as a soft delete by setting `deleted_at`.
+        async fn delete(&self, id: &str) -> Result<bool, KernelError>;
+        /// Returns the ids among `ids` that were live and are now deleted, in input order; unknown ids are skipped.
+        ///
+        /// Drivers capable of bulk deletes should override this.
+        async fn delete_many(&self, ids: &[String]) -> Result<Vec<String>, KernelError> {
+            let mut deleted = Vec::new();
+            for id in ids {
+                if self.delete(id).await? {
+                    deleted.push(id.clone());
+                }
+            }
+            Ok(deleted)
+        }
+        /// Soft-deleted records are not returned.
+        async fn list(&self, offset: u64, limit: u64) -> Result<Vec<Data>, KernelError>;
+        async fn count(&self) -> Result<u64, KernelError>;
+        /// Records whose name starts with `prefix`, in `list` order. An empty prefix matches everything.
+        async fn find_by_name_prefix(&self, prefix: &str, limit: u64) -> Result<Vec<Data>, KernelError>;
+        /// Yields every record that `list` would, without loading them all at once.
+        fn stream_all(&self) -> DataStream;
+    }
+
+    // Shared and boxed repositories are repositories too; every call goes straight to the pointee.
+    macro_rules! delegate_repository {
+        ($($ptr:ident),*) => {$(
+            #[async_trait::async_trait]
+            impl<R: Repository> Repository for $ptr<R> {
+                type Tx = R::Tx;
+
+                async fn begin(&self) -> Result<Self::Tx, KernelError> {
+                    (**self).begin().await
+                }
+
+                async fn create(&self, data: &Data) -> Result<(), KernelError> {
+                    (**self).create(data).await
+                }
+
+                async fn create_returning(&self, data: &Data) -> Result<Data, KernelError> {
+                    (**self).create_returning(data).await
+                }
+
+                async fn create_in(&self, tx: &mut Self::Tx, data: &Data) -> Result<(), KernelError> {
+                    (**self).create_in(tx, data).await
+                }
+
+                async fn create_many(&self, data: &[Data]) -> Result<(), KernelError> {
+                    (**self).create_many(data).await
+                }
+
+                async fn create_many_returning(&self, data: &[Data]) -> Result<Vec<Result<Data, KernelError>>, KernelError> {
+                    (**self).create_many_returning(data).await
+                }
+
+                async fn find_by_id(&self, id: &str) -> Result<Option<Data>, KernelError> {
+                    (**self).find_by_id(id).await
+                }
+
+                async fn find_by_ids(&self, ids: &[String]) -> Result<Vec<Data>, KernelError> {
+                    (**self).find_by_ids(ids).await
+                }
+
+                async fn find_by_id_including_deleted(&self, id: &str) -> Result<Option<Data>, KernelError> {
+                    (**self).find_by_id_including_deleted(id).await
+                }
+
+                async fn exists(&self, id: &str) -> Result<bool, KernelError> {
+                    (**self).exists(id).await
+                }
+
+                async fn update(&self, data: &Data, expected_version: u64) -> Result<bool, KernelError> {
+                    (**self).update(data, expected_version).await
+                }
+
+                async fn update_returning(&self, data: &Data, expected_version: u64) -> Result<Option<Data>, KernelError> {
+                    (**self).update_returning(data, expected_version).await
+                }
+
+                async fn upsert(&self, data: &Data) -> Result<bool, KernelError> {
+                    (**self).upsert(data).await
+                }
+
+                async fn update_fields(&self, id: &str, patch: DataPatch) -> Result<bool, KernelError> {
+                    (**self).update_fields(id, patch).await
+                }
+
+                async fn delete(&self, id: &str) -> Result<bool, KernelError> {
+                    (**self).delete(id).await
+                }
+
+                async fn delete_many(&self, ids: &[String]) -> Result<Vec<String>, KernelError> {
+                    (**self).delete_many(ids).await
+                }
+
+                async fn list(&self, offset: u64, limit: u64) -> Result<Vec<Data>, KernelError> {
+                    (**self).list(offset, limit).await
+                }
+
+                async fn count(&self) -> Result<u64, KernelError> {
+                    (**self).count().await
+                }
+
+                async fn find_by_name_prefix(&self, prefix: &str, limit: u64) -> Result<Vec<Data>, KernelError> {
+                    (**self).find_by_name_prefix(prefix, limit).await
+                }
+
+                fn stream_all(&self) -> DataStream {
+                    (**self).stream_all()
+                }
+            }
+        )*};
+    }
+
+    delegate_repository!(Arc, Box);
+
+    depend_on!(trait DependOnRepository => Repository, repository);
+
+    /// The repository read-side use cases are served from, e.g. a cache.
+    pub trait DependOnReadRepository: 'static + Send + Sync {
+        type ReadRepository: Repository;
+        fn read_repository(&self) -> &Self::ReadRepository;
+    }
+
+    /// The repository write-side use cases are sent to.
+    pub trait DependOnWriteRepository: 'static + Send + Sync {
+        type WriteRepository: Repository;
+        fn write_repository(&self) -> &Self::WriteRepository;
+    }
+
+    // Containers with a single repository serve both sides from it.
+    impl<T> DependOnReadRepository for T
+        where T: DependOnRepository
+    {
+        type ReadRepository = T::Repository;
+        fn read_repository(&self) -> &Self::ReadRepository {
+            self.repository()
+        }
+    }
+
+    impl<T> DependOnWriteRepository for T
+        where T: DependOnRepository
+    {
+        type WriteRepository = T::Repository;
+        fn write_repository(&self) -> &Self::WriteRepository {
+            self.repository()
+        }
+    }
+}
+
+/// A.k.a UseCase Layer
+pub mod application {
+    use std::{collections::BTreeMap, pin::Pin};
+
+    use futures::{Stream, StreamExt};
+    use time::OffsetDateTime;
+    use tokio_util::sync::CancellationToken;
+
+    use crate::kernel::{
+        codes::*,
+        DependOnReadRepository, DependOnWriteRepository, DependOnEventPublisher, EventPublisher, DomainEvent,
+        DependOnIdGenerator, IdGenerator, DependOnAuditLog, AuditLog, AuditAction,
+        Repository, Data, DataId, DataName, DestructData, Clock, SystemClock, DependOnClock, error::KernelError,
+    };
+
+    /// Errors surfaced to callers of the use cases; driver details are not exposed.
+    #[derive(Debug, Clone, thiserror::Error)]
+    pub enum AppError {
+        #[error("not found")]
+        NotFound,
+        #[error("conflict")]
+        Conflict,
+        #[error("validation error: {0}")]
+        Validation(String),
+        #[error("internal error")]
+        Internal,
+        #[error("cancelled")]
+        Cancelled,
+        #[error("unavailable")]
+        Unavailable,
+        #[error("batch of {len} exceeds the limit of {max}")]
+        TooLarge { len: usize, max: usize },
+    }
+
+    impl AppError {
+        /// The matching constant from `kernel::codes`.
+        pub fn code(&self) -> u16 {
+            match self {
+                AppError::NotFound => ERR_NOT_FOUND,
+                AppError::Conflict => ERR_CONFLICT,
+                AppError::Validation(_) => ERR_VALIDATION,
+                AppError::Internal => ERR_INTERNAL,
+                AppError::Cancelled => ERR_CANCELLED,
+                AppError::Unavailable => ERR_UNAVAILABLE,
+                AppError::TooLarge { .. } => ERR_TOO_LARGE,
+            }
+        }
+    }
+
+    /// Lets infallible conversions go wherever a `TryInto` with an `AppError`-compatible error is expected.
+    impl From<std::convert::Infallible> for AppError {
+        fn from(value: std::convert::Infallible) -> Self {
+            match value {}
+        }
+    }
+
+    impl From<KernelError> for AppError {
+        fn from(value: KernelError) -> Self {
+            match value {
+                KernelError::NotFound => AppError::NotFound,
+                KernelError::Conflict => AppError::Conflict,
+                KernelError::Validation(reason) => AppError::Validation(reason),
+                KernelError::Cancelled => AppError::Cancelled,
+                KernelError::Unavailable => AppError::Unavailable,
+                KernelError::TooLarge { len, max } => AppError::TooLarge { len, max },
+                KernelError::Driver(_) | KernelError::Timeout => AppError::Internal,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    #[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+    pub struct DataDto {
+        pub id: String,
+        pub name: String,
+        /// Assigned by the domain; ignored on input.
+        #[cfg_attr(feature = "serde", serde(default, with = "time::serde::rfc3339::option"))]
+        pub created_at: Option<OffsetDateTime>,
+        /// The version the record was read at, used for optimistic concurrency.
+        #[cfg_attr(feature = "serde", serde(default))]
+        pub version: u64,
+        /// Fields beyond the known ones, passed through untouched.
+        #[cfg_attr(feature = "serde", serde(flatten))]
+        pub attributes: BTreeMap<String, String>
+    }
+
+    impl From<Data> for DataDto {
+        fn from(value: Data) -> Self {
+            let DestructData {
+                id,
+                name,
+                created_at,
+                version,
+                extra,
+                ..
+            } = value.into_destruct();
+            Self { id: id.into(), name: name.into(), created_at: Some(created_at), version, attributes: extra }
+        }
+    }
+
+    /// Runs the domain validation; `created_at`, `version` and the deletion state are not carried over.
+    ///
+    /// `attributes` are copied as-is.
+    impl TryFrom<DataDto> for Data {
+        type Error = KernelError;
+        fn try_from(value: DataDto) -> Result<Self, Self::Error> {
+            value.into_domain(&SystemClock)
+        }
+    }
+
+    impl From<&Data> for DataDto {
+        fn from(value: &Data) -> Self {
+            Self {
+                id: value.id().to_string(),
+                name: value.name().to_string(),
+                created_at: Some(value.created_at()),
+                version: value.version(),
+                attributes: value.extra().clone()
+            }
+        }
+    }
+
+    /// Read-side projection of a record, shaped for display rather than for writing back.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize))]
+    #[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+    pub struct DataReadModel {
+        pub id: String,
+        /// `"<name> (<id>)"`.
+        pub display_name: String,
+        #[cfg_attr(feature = "serde", serde(with = "time::serde::rfc3339"))]
+        pub created_at: OffsetDateTime,
+        pub version: u64
+    }
+
+    impl From<&Data> for DataReadModel {
+        fn from(value: &Data) -> Self {
+            Self {
+                id: value.id().to_string(),
+                display_name: format!("{} ({})", value.name(), value.id()),
+                created_at: value.created_at(),
+                version: value.version()
+            }
+        }
+    }
+
+    pub use crate::kernel::DataPatch;
+
+    impl DataDto {
+        pub fn builder() -> DataDtoBuilder {
+            DataDtoBuilder::default()
+        }
+
+        /// Like `Data::try_from`, stamping `created_at` from `clock`.
+        pub fn into_domain(self, clock: &impl Clock) -> Result<Data, KernelError> {
+            let DataDto { id, name, attributes, .. } = self;
+            Ok(Data::try_new(id.parse()?, name, clock)?.with_extra(attributes))
+        }
+
+        /// Reports every field problem at once instead of stopping at the first.
+        pub fn validate(&self) -> Result<(), Vec<String>> {
+            let mut problems = Vec::new();
+            if let Err(KernelError::Validation(reason)) = DataId::new(self.id.as_str()) {
+                problems.push(reason);
+            }
+            if let Err(KernelError::Validation(reason)) = DataName::new(self.name.as_str()) {
+                problems.push(reason);
+            }
+            if problems.is_empty() { Ok(()) } else { Err(problems) }
+        }
+    }
+
+    #[derive(Debug, Default)]
+    pub struct DataDtoBuilder {
+        id: Option<String>,
+        name: Option<String>
+    }
+
+    impl DataDtoBuilder {
+        pub fn id(mut self, id: impl Into<String>) -> Self {
+            self.id = Some(id.into());
+            self
+        }
+
+        pub fn name(mut self, name: impl Into<String>) -> Self {
+            self.name = Some(name.into());
+            self
+        }
+
+        pub fn build(self) -> Result<DataDto, AppError> {
+            let id = self.id.ok_or_else(|| AppError::Validation("id is required".to_string()))?;
+            let name = self.name.ok_or_else(|| AppError::Validation("name is required".to_string()))?;
+            Ok(DataDto { id, name, created_at: None, version: 0, attributes: BTreeMap::new() })
+        }
+    }
+
+    /// No blanket impl is provided so that implementors can override `REJECT_DUPLICATES`.
+    #[async_trait::async_trait]
+    pub trait CreateDataService: 'static + Send + Sync
+        + DependOnWriteRepository
+        + DependOnEventPublisher
+        + DependOnIdGenerator
+        + DependOnAuditLog
+        + DependOnClock
+    {
+        const REJECT_DUPLICATES: bool = false;
+
+        #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(id = %obj.id)))]
+        async fn create(&self, obj: DataDto) -> Result<DataDto, AppError> {
+            let res = async {
+                let data = obj.into_domain(self.clock())?;
+                if Self::REJECT_DUPLICATES && self.write_repository().exists(data.id().as_ref()).await? {
+                    return Err(AppError::Conflict);
+                }
+                let stored = self.write_repository().create_returning(&data).await?;
+                self.event_publisher().publish(DomainEvent::DataCreated { id: stored.id().clone() }).await;
+                self.audit_log().record(AuditAction::Created, stored.id().as_ref());
+                Ok(stored.into())
+            }.await;
+            #[cfg(feature = "metrics")]
+            record_create(&res);
+            res
+        }
+
+        /// Like `create`, with the id minted by the injected `IdGenerator`.
+        async fn create_with_generated_id(&self, name: String) -> Result<DataDto, AppError> {
+            let id = self.id_generator().generate();
+            self.create(DataDto { id: id.into(), name, created_at: None, version: 0, attributes: BTreeMap::new() }).await
+        }
+
+        /// Like `create`, but gives up with `Cancelled` as soon as `cancel` fires.
+        async fn create_cancellable(&self, obj: DataDto, cancel: Option<&CancellationToken>) -> Result<DataDto, AppError> {
+            let Some(cancel) = cancel else {
+                return self.create(obj).await;
+            };
+            if cancel.is_cancelled() {
+                return Err(KernelError::Cancelled.into());
+            }
+            tokio::select! {
+                biased;
+                _ = cancel.cancelled() => Err(KernelError::Cancelled.into()),
+                res = self.create(obj) => res,
+            }
+        }
+    }
+
+    depend_on!(trait DependOnCreateDataService => CreateDataService, create_simple_data_service);
+
+    /// Bumps `data_created_total`, or `data_create_errors_total` labelled with the error's `code`.
+    #[cfg(feature = "metrics")]
+    fn record_create<T>(res: &Result<T, AppError>) {
+        match res {
+            Ok(_) => metrics::counter!("data_created_total").increment(1),
+            Err(e) => metrics::counter!("data_create_errors_total", "code" => e.code().to_string()).increment(1)
+        }
+    }
+
+    /// No blanket impl is provided so that implementors can override `MAX_BATCH`.
+    #[async_trait::async_trait]
+    pub trait BatchCreateDataService: 'static + Send + Sync
+        + DependOnWriteRepository
+        + DependOnAuditLog
+        + DependOnClock
+    {
+        const MAX_BATCH: usize = 1000;
+
+        /// Batches over `MAX_BATCH` are rejected as a whole before anything is validated or written.
+        ///
+        /// Otherwise every row is validated first, the valid ones are written in one `create_many_returning`,
+        /// and every row gets its own result, in input order; an invalid or conflicting row does not affect the others.
+        async fn create_many(&self, objs: Vec<DataDto>) -> Result<Vec<Result<DataDto, AppError>>, AppError> {
+            if objs.len() > Self::MAX_BATCH {
+                return Err(KernelError::TooLarge { len: objs.len(), max: Self::MAX_BATCH }.into());
+            }
+            let mut rejected = Vec::with_capacity(objs.len());
+            let mut valid = Vec::with_capacity(objs.len());
+            for obj in objs {
+                match obj.into_domain(self.clock()) {
+                    Ok(data) => {
+                        rejected.push(None);
+                        valid.push(data);
+                    },
+                    Err(e) => rejected.push(Some(e.into()))
+                }
+            }
+            let mut stored = self.write_repository().create_many_returning(&valid).await?.into_iter();
+            let mut results = Vec::with_capacity(rejected.len());
+            for rejected in rejected {
+                let res = match rejected {
+                    Some(e) => Err(e),
+                    None => stored.next().expect("the repository reports every row").map_err(Into::into)
+                };
+                if let Ok(data) = &res {
+                    self.audit_log().record(AuditAction::Created, data.id().as_ref());
+                }
+                results.push(res.map(Into::into));
+            }
+            Ok(results)
+        }
+    }
+
+    depend_on!(trait DependOnBatchCreateDataService => BatchCreateDataService, batch_create_data_service);
+
+    #[async_trait::async_trait]
+    pub trait GetDataService: 'static + Send + Sync
+        + DependOnReadRepository
+    {
+        async fn get(&self, id: String) -> Result<DataDto, AppError> {
+            self.read_repository().find_by_id(&id).await?
+                .map(Into::into)
+                .ok_or(AppError::NotFound)
+        }
+    }
+
+    // Default Impl
+    impl<T> GetDataService for T
+        where T: DependOnReadRepository {}
+
+    depend_on!(trait DependOnGetDataService => GetDataService, get_data_service);
+
+    #[async_trait::async_trait]
+    pub trait BatchGetDataService: 'static + Send + Sync
+        + DependOnReadRepository
+    {
+        /// Unknown ids are skipped rather than reported, and the order of `ids` is not kept.
+        async fn get_many(&self, ids: Vec<String>) -> Result<Vec<DataDto>, AppError> {
+            let data = self.read_repository().find_by_ids(&ids).await?;
+            Ok(data.into_iter().map(Into::into).collect())
+        }
+    }
+
+    // Default Impl
+    impl<T> BatchGetDataService for T
+        where T: DependOnReadRepository {}
+
+    depend_on!(trait DependOnBatchGetDataService => BatchGetDataService, batch_get_data_service);
+
+    #[async_trait::async_trait]
+    pub trait GetDataReadModelService: 'static + Send + Sync
+        + DependOnReadRepository
+    {
+        async fn get_read_model(&self, id: String) -> Result<DataReadModel, AppError> {
+            self.read_repository().find_by_id(&id).await?
+                .as_ref()
+                .map(Into::into)
+                .ok_or(AppError::NotFound)
+        }
+    }
+
+    // Default Impl
+    impl<T> GetDataReadModelService for T
+        where T: DependOnReadRepository {}
+
+    depend_on!(trait DependOnGetDataReadModelService => GetDataReadModelService, get_data_read_model_service);
+
+    #[async_trait::async_trait]
+    pub trait UpdateDataService: 'static + Send + Sync
+        + DependOnWriteRepository
+        + DependOnAuditLog
+        + DependOnClock
+    {
+        /// `obj.version` must be the version last read; stale versions are rejected as `Conflict`.
+        ///
+        /// Returns the record as stored afterwards, so fields the client cannot set come from the repository.
+        async fn update(&self, obj: DataDto) -> Result<DataDto, AppError> {
+            let version = obj.version;
+            let data = obj.into_domain(self.clock())?;
+            let stored = self.write_repository().update_returning(&data, version).await?
+                .ok_or(AppError::NotFound)?;
+            self.audit_log().record(AuditAction::Updated, stored.id().as_ref());
+            Ok(stored.into())
+        }
+
+        /// Applies only the `Some` fields of `patch`. An empty patch writes nothing.
+        async fn patch(&self, id: String, patch: DataPatch) -> Result<DataDto, AppError> {
+            let mut data = self.write_repository().find_by_id(&id).await?
+                .ok_or(AppError::NotFound)?;
+            if patch.is_empty() {
+                return Ok(data.into());
+            }
+            patch.apply(&mut data)?;
+            let version = data.version();
+            if !self.write_repository().update(&data, version).await? {
+                return Err(AppError::NotFound);
+            }
+            self.audit_log().record(AuditAction::Updated, data.id().as_ref());
+            Ok(data.with_version(version + 1).into())
+        }
+    }
+
+    // Default Impl
+    impl<T> UpdateDataService for T
+        where T: DependOnWriteRepository + DependOnAuditLog + DependOnClock {}
+
+    depend_on!(trait DependOnUpdateDataService => UpdateDataService, update_data_service);
+
+    #[async_trait::async_trait]
+    pub trait UpsertDataService: 'static + Send + Sync
+        + DependOnWriteRepository
+        + DependOnAuditLog
+        + DependOnClock
+    {
+        /// Returns `true` if the record was newly inserted.
+        async fn upsert(&self, obj: DataDto) -> Result<bool, AppError> {
+            let data = obj.into_domain(self.clock())?;
+            let inserted = self.write_repository().upsert(&data).await?;
+            let action = if inserted { AuditAction::Created } else { AuditAction::Updated };
+            self.audit_log().record(action, data.id().as_ref());
+            Ok(inserted)
+        }
+    }
+
+    // Default Impl
+    impl<T> UpsertDataService for T
+        where T: DependOnWriteRepository + DependOnAuditLog + DependOnClock {}
+
+    depend_on!(trait DependOnUpsertDataService => UpsertDataService, upsert_data_service);
+
+    #[async_trait::async_trait]
+    pub trait DeleteDataService: 'static + Send + Sync
+        + DependOnWriteRepository
+        + DependOnAuditLog
+    {
+        /// Deleting an unknown id is `Ok` and leaves no audit entry.
+        async fn delete(&self, id: String) -> Result<(), AppError> {
+            match self.write_repository().delete(&id).await {
+                Ok(false) | Err(KernelError::NotFound) => Ok(()),
+                Ok(true) => {
+                    self.audit_log().record(AuditAction::Deleted, &id);
+                    Ok(())
+                },
+                Err(e) => Err(e.into())
+            }
+        }
+    }
+
+    // Default Impl
+    impl<T> DeleteDataService for T
+        where T: DependOnWriteRepository + DependOnAuditLog {}
+
+    depend_on!(trait DependOnDeleteDataService => DeleteDataService, delete_data_service);
+
+    #[async_trait::async_trait]
+    pub trait BulkDeleteDataService: 'static + Send + Sync
+        + DependOnWriteRepository
+        + DependOnAuditLog
+    {
+        /// Returns the number of records actually deleted; exactly those are audited.
+        async fn delete_many(&self, ids: Vec<String>) -> Result<u64, AppError> {
+            let deleted = self.write_repository().delete_many(&ids).await?;
+            for id in &deleted {
+                self.audit_log().record(AuditAction::Deleted, id);
+            }
+            Ok(deleted.len() as u64)
+        }
+    }
+
+    // Default Impl
+    impl<T> BulkDeleteDataService for T
+        where T: DependOnWriteRepository + DependOnAuditLog {}
+
+    depend_on!(trait DependOnBulkDeleteDataService => BulkDeleteDataService, bulk_delete_data_service);
+
+    /// No blanket impl is provided so that implementors can override `MAX_LIMIT`.
+    #[async_trait::async_trait]
+    pub trait ListDataService: 'static + Send + Sync
+        + DependOnReadRepository
+    {
+        const MAX_LIMIT: u64 = 100;
+
+        async fn list(&self, offset: u64, limit: u64) -> Result<Vec<DataDto>, AppError> {
+            let limit = limit.min(Self::MAX_LIMIT);
+            let data = self.read_repository().list(offset, limit).await?;
+            Ok(data.into_iter().map(Into::into).collect())
+        }
+    }
+
+    depend_on!(trait DependOnListDataService => ListDataService, list_data_service);
+
+    #[async_trait::async_trait]
+    pub trait CountDataService: 'static + Send + Sync
+        + DependOnReadRepository
+    {
+        async fn count(&self) -> Result<u64, AppError> {
+            Ok(self.read_repository().count().await?)
+        }
+    }
+
+    // Default Impl
+    impl<T> CountDataService for T
+        where T: DependOnReadRepository {}
+
+    depend_on!(trait DependOnCountDataService => CountDataService, count_data_service);
+
+    #[async_trait::async_trait]
+    pub trait SearchDataService: 'static + Send + Sync
+        + DependOnReadRepository
+    {
+        /// Typeahead lookup on `name`.
+        async fn search(&self, prefix: &str, limit: u64) -> Result<Vec<DataDto>, AppError> {
+            let data = self.read_repository().find_by_name_prefix(prefix, limit).await?;
+            Ok(data.into_iter().map(Into::into).collect())
+        }
+    }
+
+    // Default Impl
+    impl<T> SearchDataService for T
+        where T: DependOnReadRepository {}
+
+    depend_on!(trait DependOnSearchDataService => SearchDataService, search_data_service);
+
+    pub type DataDtoStream = Pin<Box<dyn Stream<Item = Result<DataDto, AppError>> + Send>>;
+
+    pub trait StreamDataService: 'static + Send + Sync
+        + DependOnReadRepository
+    {
+        fn stream(&self) -> DataDtoStream {
+            Box::pin(self.read_repository().stream_all()
+                .map(|res| res.map(Into::into).map_err(Into::into)))
+        }
+    }
+
+    // Default Impl
+    impl<T> StreamDataService for T
+        where T: DependOnReadRepository {}
+
+    depend_on!(trait DependOnStreamDataService => StreamDataService, stream_data_service);
+}
+
+/// A.k.a DI Container
+pub mod inject {
+    use std::sync::Arc;
+
+    use crate::{
+        kernel::{
+            DependOnRepository, Repository, DependOnEventPublisher, NoopEventPublisher,
+            DependOnIdGenerator, UuidGenerator, DependOnAuditLog, NoopAuditLog, DependOnClock, SystemClock,
+            error::KernelError,
+        },
+        driver::{DataRepository, Pool, StdoutSink},
+        application::{
+            CreateDataService, DependOnCreateDataService, BatchCreateDataService, DependOnBatchCreateDataService,
+            DependOnGetDataService, DependOnBatchGetDataService, DependOnGetDataReadModelService, DependOnUpdateDataService, DependOnUpsertDataService,
+            DependOnDeleteDataService, DependOnBulkDeleteDataService,
+            ListDataService, DependOnListDataService, DependOnCountDataService, DependOnSearchDataService,
+            DependOnStreamDataService,
+        },
+    };
+
+    /// How much the driver logs; anything but `Off` goes to stdout.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+    #[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+    pub enum LogLevel {
+        #[default]
+        Off,
+        Debug,
+    }
+
+    /// Everything `Handler::from_config` needs, e.g. loaded from a JSON file.
+    #[derive(Debug, Clone)]
+    #[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+    pub struct HandlerConfig {
+        pub database_url: String,
+        pub pool_size: u32,
+        #[cfg_attr(feature = "serde", serde(default))]
+        pub log_level: LogLevel,
+    }
+
+    #[derive(Clone)]
+    pub struct Handler {
+        repo: DataRepository,
+        publisher: NoopEventPublisher,
+        id_generator: UuidGenerator,
+        audit_log: NoopAuditLog,
+        clock: SystemClock
+    }
+    impl Handler {
+        pub fn new(pool: Pool) -> Self {
+            Self {
+                repo: DataRepository::new(pool),
+                publisher: NoopEventPublisher,
+                id_generator: UuidGenerator,
+                audit_log: NoopAuditLog,
+                clock: SystemClock
+            }
+        }
+
+        /// Fails if the pool settings are rejected by `Pool::connect`.
+        pub fn from_config(cfg: HandlerConfig) -> Result<Self, KernelError> {
+            let handler = Self::new(Pool::connect(&cfg.database_url, cfg.pool_size)?);
+            Ok(match cfg.log_level {
+                LogLevel::Off => handler,
+                LogLevel::Debug => Self { repo: handler.repo.with_sink(StdoutSink), ..handler }
+            })
+        }
+
+        /// Builds a handler over an in-memory pool that logs to stdout, for examples.
+        pub fn init() -> Self {
+            let handler = Self::new(Pool::in_memory());
+            Self { repo: handler.repo.with_sink(StdoutSink), ..handler }
+        }
+
+        pub async fn health_check(&self) -> Result<(), KernelError> {
+            self.repo.0.ping().await
+        }
+
+        /// Wraps the handler for sharing across tasks; every `DependOn*` impl still applies.
+        pub fn shared(self) -> Arc<Self> {
+            Arc::new(self)
+        }
+    }
+    /// Same as `Handler::init`, so generic code can write `H::default()`.
+    impl Default for Handler {
+        fn default() -> Self {
+            Self::init()
+        }
+    }
+    impl DependOnRepository for Handler {
+        type Repository = DataRepository;
+        fn repository(&self) -> &Self::Repository {
+            &self.repo
+        }
+    }
+    impl DependOnEventPublisher for Handler {
+        type EventPublisher = NoopEventPublisher;
+        fn event_publisher(&self) -> &Self::EventPublisher {
+            &self.publisher
+        }
+    }
+    impl DependOnIdGenerator for Handler {
+        type IdGenerator = UuidGenerator;
+        fn id_generator(&self) -> &Self::IdGenerator {
+            &self.id_generator
+        }
+    }
+    impl DependOnAuditLog for Handler {
+        type AuditLog = NoopAuditLog;
+        fn audit_log(&self) -> &Self::AuditLog {
+            &self.audit_log
+        }
+    }
+    impl DependOnClock for Handler {
+        type Clock = SystemClock;
+        fn clock(&self) -> &Self::Clock {
+            &self.clock
+        }
+    }
+    impl CreateDataService for Handler {}
+    depend_on!(impl DependOnCreateDataService for Handler => CreateDataService, create_simple_data_service);
+    impl BatchCreateDataService for Handler {}
+    depend_on!(impl DependOnBatchCreateDataService for Handler => BatchCreateDataService, batch_create_data_service);
+    depend_on!(impl DependOnGetDataService for Handler => GetDataService, get_data_service);
+    depend_on!(impl DependOnBatchGetDataService for Handler => BatchGetDataService, batch_get_data_service);
+    depend_on!(impl DependOnGetDataReadModelService for Handler => GetDataReadModelService, get_data_read_model_service);
+    depend_on!(impl DependOnUpdateDataService for Handler => UpdateDataService, update_data_service);
+    depend_on!(impl DependOnUpsertDataService for Handler => UpsertDataService, upsert_data_service);
+    depend_on!(impl DependOnDeleteDataService for Handler => DeleteDataService, delete_data_service);
+    depend_on!(impl DependOnBulkDeleteDataService for Handler => BulkDeleteDataService, bulk_delete_data_service);
+    impl ListDataService for Handler {}
+    depend_on!(impl DependOnListDataService for Handler => ListDataService, list_data_service);
+    depend_on!(impl DependOnCountDataService for Handler => CountDataService, count_data_service);
+    depend_on!(impl DependOnSearchDataService for Handler => SearchDataService, search_data_service);
+    depend_on!(impl DependOnStreamDataService for Handler => StreamDataService, stream_data_service);
+}
+
+/// Test doubles for downstream unit tests
+#[cfg(feature = "testing")]
+pub mod testing {
+    use std::sync::{Arc, Mutex};
+
+    use futures::stream;
+
+    use crate::kernel::{Repository, Transaction, AuditLog, AuditAction, Data, DataStream, error::KernelError};
+
+    /// Keeps every audit entry in order, for asserting on the trail a service leaves.
+    #[derive(Debug, Default)]
+    pub struct InMemoryAuditLog {
+        entries: Mutex<Vec<(AuditAction, String)>>
+    }
+
+    impl InMemoryAuditLog {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn entries(&self) -> Vec<(AuditAction, String)> {
+            self.entries.lock().unwrap().clone()
+        }
+    }
+
+    impl AuditLog for InMemoryAuditLog {
+        fn record(&self, action: AuditAction, id: &str) {
+            self.entries.lock().unwrap().push((action, id.to_string()));
+        }
+    }
+
+    /// Records every `Data` passed to `create`, or committed through `create_in`; `update` rewrites
+    /// the recorded record and `delete` drops it.
+    ///
+    /// `DependOnRepository` only hands out `&Self::Repository`, so the recorded state sits
+    /// behind a `Mutex` and is cleared through `&self` with `reset`.
+    #[derive(Default)]
+    pub struct MockRepository {
+        calls: Arc<Mutex<Vec<Data>>>
+    }
+
+    pub struct MockTransaction {
+        pending: Vec<Data>,
+        store: Arc<Mutex<Vec<Data>>>
+    }
+
+    #[async_trait::async_trait]
+    impl Transaction for MockTransaction {
+        async fn commit(self) -> Result<(), KernelError> {
+            self.store.lock().unwrap().extend(self.pending);
+            Ok(())
+        }
+
+        async fn rollback(self) -> Result<(), KernelError> {
+            Ok(())
+        }
+    }
+
+    impl MockRepository {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn calls(&self) -> Vec<Data> {
+            self.calls.lock().unwrap().clone()
+        }
+
+        /// Forgets every recorded call, e.g. between test cases sharing one container.
+        pub fn reset(&self) {
+            self.calls.lock().unwrap().clear();
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Repository for MockRepository {
+        type Tx = MockTransaction;
+
+        async fn begin(&self) -> Result<Self::Tx, KernelError> {
+            Ok(MockTransaction { pending: Vec::new(), store: Arc::clone(&self.calls) })
+        }
+
+        async fn create(&self, data: &Data) -> Result<(), KernelError> {
+            self.calls.lock().unwrap().push(data.clone());
+            Ok(())
+        }
+
+        async fn create_in(&self, tx: &mut Self::Tx, data: &Data) -> Result<(), KernelError> {
+            tx.pending.push(data.clone());
+            Ok(())
+        }
+
+        async fn find_by_id(&self, id: &str) -> Result<Option<Data>, KernelError> {
+            Ok(self.calls.lock().unwrap().iter().rev().find(|data| data.id().as_ref() == id).cloned())
+        }
+
+        /// Applies `data` to the latest record with its id under the next version; `expected_version` is not checked.
+        async fn update(&self, data: &Data, _expected_version: u64) -> Result<bool, KernelError> {
+            let mut calls = self.calls.lock().unwrap();
+            let Some(stored) = calls.iter_mut().rev().find(|call| call.id() == data.id()) else {
+                return Ok(false);
+            };
+            let version = stored.version() + 1;
+            *stored = stored.clone().with_contents_of(data).with_version(version);
+            Ok(true)
+        }
+
+        async fn delete(&self, id: &str) -> Result<bool, KernelError> {
+            let mut calls = self.calls.lock().unwrap();
+            let before = calls.len();
+            calls.retain(|call| call.id().as_ref() != id);
+            Ok(calls.len() < before)
+        }
+
+        async fn list(&self, offset: u64, limit: u64) -> Result<Vec<Data>, KernelError> {
+            Ok(self.calls.lock().unwrap().iter()
+                .skip(offset as usize)
+                .take(limit as usize)
+                .cloned()
+                .collect())
+        }
+
+        async fn count(&self) -> Result<u64, KernelError> {
+            Ok(self.calls.lock().unwrap().len() as u64)
+        }
+
+        fn stream_all(&self) -> DataStream {
+            Box::pin(stream::iter(self.calls().into_iter().map(Ok)))
+        }
+
+        async fn find_by_name_prefix(&self, prefix: &str, limit: u64) -> Result<Vec<Data>, KernelError> {
+            Ok(self.calls.lock().unwrap().iter()
+                .filter(|data| data.name().as_ref().starts_with(prefix))
+                .take(limit as usize)
+                .cloned()
+                .collect())
+        }
+    }
+}
+
+/// A.k.a Presentation Layer
+pub mod adaptor {
+    use std::{
+        any::type_name,
+        collections::HashMap,
+        fmt,
+        future::{Future, IntoFuture},
+        marker::PhantomData,
+        pin::Pin,
+        time::{Duration, Instant},
+    };
+
+    use futures::{future, Stream, StreamExt};
+    use tokio::sync::Semaphore;
+    use tokio_util::sync::CancellationToken;
+
+    use crate::application::{DataDto, AppError};
+    use crate::kernel::{codes::*, error::KernelError, RequestContext};
+
+    pub use controller_test::port::{InPort, Then};
+
+    /// An `InPort` that may reject its input before it reaches the use case.
+    pub trait FallibleInPort<I>: 'static + Sync + Send {
+        type Dto;
+        fn emit(&self, input: I) -> Result<Self::Dto, AppError>;
+    }
+
+    pub trait OutPort<I>: 'static + Sync + Send {
+        type ViewModel;
+        fn emit(&self, input: I) -> Self::ViewModel;
+
+        /// Post-processes the view model with `g`.
+        fn map<G, V>(self, g: G) -> MappedOutPort<Self, G, V>
+            where Self: Sized,
+                  G: Fn(Self::ViewModel) -> V + 'static + Send + Sync
+        {
+            MappedOutPort { inner: self, g, _view: PhantomData }
+        }
+    }
+
+    pub struct MappedOutPort<P, G, V> {
+        inner: P,
+        g: G,
+        _view: PhantomData<fn() -> V>
+    }
+
+    impl<I, P, G, V> OutPort<I> for MappedOutPort<P, G, V>
+        where P: OutPort<I>,
+              G: Fn(P::ViewModel) -> V + 'static + Send + Sync,
+              V: 'static
+    {
+        type ViewModel = V;
+        fn emit(&self, input: I) -> Self::ViewModel {
+            (self.g)(self.inner.emit(input))
+        }
+    }
+
+    /// Presents the same input through two presenters, e.g. a response and an audit entry.
+    pub struct TeePresenter<P1, P2>(pub P1, pub P2);
+
+    impl<I: Clone, P1, P2> OutPort<I> for TeePresenter<P1, P2>
+        where P1: OutPort<I>,
+              P2: OutPort<I>
+    {
+        type ViewModel = (P1::ViewModel, P2::ViewModel);
+        fn emit(&self, input: I) -> Self::ViewModel {
+            (self.0.emit(input.clone()), self.1.emit(input))
+        }
+    }
+
+    /// A presenter whose rendering can fail, e.g. on a serialization error.
+    pub trait FallibleOutPort<I>: 'static + Sync + Send {
+        type ViewModel;
+        type Error;
+        fn try_emit(&self, input: I) -> Result<Self::ViewModel, Self::Error>;
+    }
+
+    /// A presenter that also sees side-channel data `M` the handler returned next to its output,
+    /// e.g. whether the result came from a cache.
+    pub trait MetaOutPort<I, M>: 'static + Sync + Send {
+        type ViewModel;
+        fn emit(&self, input: I, meta: M) -> Self::ViewModel;
+    }
+
+    pub type ChunkStream<C> = Pin<Box<dyn Stream<Item = C> + Send>>;
+
+    /// A presenter that renders a stream of outputs incrementally, e.g. for server-sent events.
+    pub trait StreamOutPort<I>: 'static + Sync + Send {
+        type Chunk;
+        fn emit(&self, input: ChunkStream<I>) -> ChunkStream<Self::Chunk>;
+    }
+
+    #[derive(Debug)]
+    pub struct PresentationalDataA {
+        pub id: String,
+        pub name: String
+    }
+    
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct PresenterA;
+    
+    impl OutPort<Result<DataDto, AppError>> for PresenterA {
+        type ViewModel = Result<PresentationalDataA, AppError>;
+        fn emit(&self, input: Result<DataDto, AppError>) -> Self::ViewModel {
+            match input {
+                Ok(input) => {
+                    Ok(PresentationalDataA {
+                        id: input.id,
+                        name: input.name
+                    })
+                },
+                Err(e) => {
+                    Err(e)
+                }
+            }
+        }
+    }
+    
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct PresenterB;
+    
+    impl OutPort<Result<DataDto, AppError>> for PresenterB {
+        type ViewModel = Result<String, AppError>;
+        fn emit(&self, input: Result<DataDto, AppError>) -> Self::ViewModel {
+            match input {
+                Ok(input) => {
+                    Ok(format!("{:?}", input))
+                },
+                Err(e) => {
+                    Err(e)
+                }
+            }
+        }
+    }
+
+    /// Renders list results as CSV with an `id,name,version` header, quoting per RFC 4180.
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct CsvPresenter;
+
+    impl CsvPresenter {
+        fn escape(field: &str) -> String {
+            if field.contains([',', '"', '\r', '\n']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_string()
+            }
+        }
+    }
+
+    impl OutPort<Result<Vec<DataDto>, AppError>> for CsvPresenter {
+        type ViewModel = Result<String, AppError>;
+        fn emit(&self, input: Result<Vec<DataDto>, AppError>) -> Self::ViewModel {
+            let rows = input?;
+            let mut csv = String::from("id,name,version\r\n");
+            for row in rows {
+                csv.push_str(&format!("{},{},{}\r\n", Self::escape(&row.id), Self::escape(&row.name), row.version));
+            }
+            Ok(csv)
+        }
+    }
+
+    /// Translations keyed by locale, then by message key (`not_found`, `conflict`, `validation`,
+    /// `internal`, `cancelled`, `unavailable`, `too_large`).
+    pub type MessageCatalog = HashMap<String, HashMap<String, String>>;
+
+    /// Like `PresenterA`, with errors rendered as messages for `locale`.
+    ///
+    /// Missing locales or keys fall back to the `en` entry, then to the error's own message.
+    pub struct LocalizedPresenter {
+        locale: String,
+        catalog: MessageCatalog
+    }
+
+    impl LocalizedPresenter {
+        pub const FALLBACK_LOCALE: &'static str = "en";
+
+        pub fn new(locale: &str, catalog: MessageCatalog) -> Self {
+            Self { locale: locale.to_string(), catalog }
+        }
+
+        fn key(e: &AppError) -> &'static str {
+            match e {
+                AppError::NotFound => "not_found",
+                AppError::Conflict => "conflict",
+                AppError::Validation(_) => "validation",
+                AppError::Internal => "internal",
+                AppError::Cancelled => "cancelled",
+                AppError::Unavailable => "unavailable",
+                AppError::TooLarge { .. } => "too_large",
+            }
+        }
+
+        fn lookup(&self, locale: &str, key: &str) -> Option<&str> {
+            self.catalog.get(locale)?.get(key).map(String::as_str)
+        }
+
+        pub fn message(&self, e: &AppError) -> String {
+            let key = Self::key(e);
+            let message = self.lookup(&self.locale, key)
+                .or_else(|| self.lookup(Self::FALLBACK_LOCALE, key));
+            match (message, e) {
+                (Some(message), AppError::Validation(reason)) => format!("{}: {}", message, reason),
+                (Some(message), _) => message.to_string(),
+                (None, _) => e.to_string()
+            }
+        }
+    }
+
+    impl OutPort<Result<DataDto, AppError>> for LocalizedPresenter {
+        type ViewModel = Result<PresentationalDataA, String>;
+        fn emit(&self, input: Result<DataDto, AppError>) -> Self::ViewModel {
+            match input {
+                Ok(input) => {
+                    Ok(PresentationalDataA {
+                        id: input.id,
+                        name: input.name
+                    })
+                },
+                Err(e) => {
+                    Err(self.message(&e))
+                }
+            }
+        }
+    }
+
+    /// Serializes the DTO as JSON.
+    ///
+    /// When `envelope_errors` is set, errors are rendered as `{ "error": <code>, "message": <message> }`
+    /// in the `Ok` branch instead of being propagated as `Err`. `code` is `AppError::code`.
+    #[cfg(feature = "serde")]
+    pub struct JsonPresenter {
+        envelope_errors: bool
+    }
+
+    #[cfg(feature = "serde")]
+    impl JsonPresenter {
+        pub fn new(envelope_errors: bool) -> Self {
+            Self { envelope_errors }
+        }
+
+        /// Serializes `Ok` values; a serialization failure becomes `AppError::Internal`.
+        fn render<T: serde::Serialize>(&self, input: Result<T, AppError>) -> Result<String, AppError> {
+            input.and_then(|input| serde_json::to_string(&input).map_err(|_| AppError::Internal))
+        }
+
+        fn envelope(&self, e: &AppError) -> String {
+            serde_json::json!({ "error": e.code(), "message": e.to_string() }).to_string()
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    impl OutPort<Result<DataDto, AppError>> for JsonPresenter {
+        type ViewModel = Result<String, AppError>;
+        fn emit(&self, input: Result<DataDto, AppError>) -> Self::ViewModel {
+            match self.render(input) {
+                Err(e) if self.envelope_errors => {
+                    Ok(self.envelope(&e))
+                },
+                res => res
+            }
+        }
+    }
+
+    /// An `OutPort` whose view model is an HTTP status code paired with a body.
+    pub trait HttpOutPort<I>: OutPort<I, ViewModel = (u16, String)> {}
+
+    impl<I, T> HttpOutPort<I> for T
+        where T: OutPort<I, ViewModel = (u16, String)> {}
+
+    pub fn status_code(e: &AppError) -> u16 {
+        e.code()
+    }
+
+    /// Pairs the `JsonPresenter` body with a status code, `200` on success.
+    ///
+    /// A body that fails to serialize is reported as `500` rather than panicking.
+    #[cfg(feature = "serde")]
+    pub struct HttpPresenter {
+        json: JsonPresenter
+    }
+
+    #[cfg(feature = "serde")]
+    impl HttpPresenter {
+        pub fn new() -> Self {
+            Self { json: JsonPresenter::new(true) }
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    impl Default for HttpPresenter {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    impl OutPort<Result<DataDto, AppError>> for HttpPresenter {
+        type ViewModel = (u16, String);
+        fn emit(&self, input: Result<DataDto, AppError>) -> Self::ViewModel {
+            match self.json.render(input) {
+                Ok(body) => (OK, body),
+                Err(e) => (status_code(&e), self.json.envelope(&e))
+            }
+        }
+    }
+
+    type LogFn<O> = fn(&'static str, Duration, &O);
+
+    /// Returned alongside the view model by `handle_with_metrics`.
+    #[derive(Debug, Clone, Copy)]
+    pub struct HandleMetrics {
+        pub elapsed: Duration,
+        /// Whether the handler produced `Ok`.
+        pub success: bool,
+    }
+
+    /// Emits the single per-invocation event enabled by `with_logging`; failures carry `AppError::code`.
+    #[cfg(feature = "tracing")]
+    fn log_outcome<R>(input: &'static str, elapsed: Duration, output: &Result<R, AppError>) {
+        match output {
+            Ok(_) => tracing::info!(input, ?elapsed, outcome = "ok", "controller"),
+            Err(e) => tracing::info!(input, ?elapsed, outcome = "error", code = e.code(), "controller")
+        }
+    }
+
+    /// Renders each record as one line of JSON; errors become `{ "error": <code>, "message": <message> }` lines.
+    #[cfg(feature = "serde")]
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct JsonLinesPresenter;
+
+    #[cfg(feature = "serde")]
+    impl StreamOutPort<Result<DataDto, AppError>> for JsonLinesPresenter {
+        type Chunk = String;
+        fn emit(&self, input: ChunkStream<Result<DataDto, AppError>>) -> ChunkStream<Self::Chunk> {
+            let json = JsonPresenter::new(true);
+            Box::pin(input.map(move |item| {
+                let line = json.render(item).unwrap_or_else(|e| json.envelope(&e));
+                format!("{}\n", line)
+            }))
+        }
+    }
+
+    /// Renders errors as RFC 7807 `application/problem+json` bodies in the `Err` branch.
+    #[cfg(feature = "serde")]
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct ProblemJsonPresenter;
+
+    #[cfg(feature = "serde")]
+    impl OutPort<Result<DataDto, AppError>> for ProblemJsonPresenter {
+        type ViewModel = Result<String, String>;
+        fn emit(&self, input: Result<DataDto, AppError>) -> Self::ViewModel {
+            let rendered = input.and_then(|input| serde_json::to_string(&input).map_err(|_| AppError::Internal));
+            match rendered {
+                Ok(body) => {
+                    Ok(body)
+                },
+                Err(e) => {
+                    let title = match e {
+                        AppError::NotFound => "Not Found",
+                        AppError::Conflict => "Conflict",
+                        AppError::Validation(_) => "Bad Request",
+                        AppError::Internal => "Internal Server Error",
+                        AppError::Cancelled => "Client Closed Request",
+                        AppError::Unavailable => "Service Unavailable",
+                        AppError::TooLarge { .. } => "Payload Too Large",
+                    };
+                    Err(serde_json::json!({
+                        "type": "about:blank",
+                        "title": title,
+                        "status": status_code(&e),
+                        "detail": e.to_string(),
+                    }).to_string())
+                }
+            }
+        }
+    }
+
+    pub struct _Controller<T, P, I, D, O> {
+        transformer: T,
+        presenter: P,
+        logger: Option<LogFn<O>>,
+        _in: PhantomData<I>,
+        _trans: PhantomData<D>,
+        _out: PhantomData<O>
+    }
+
+    impl<T, P, I, D, O> _Controller<T, P, I, D, O> {
+        pub fn new(transformer: T, presenter: P) -> Self {
+            Self { transformer, presenter, logger: None, _in: PhantomData, _trans: PhantomData, _out: PhantomData }
+        }
+
+        pub fn builder() -> ControllerBuilder<Missing, Missing> {
+            ControllerBuilder { transformer: Missing, presenter: Missing }
+        }
+
+        fn started(&self) -> Option<Instant> {
+            self.logger.map(|_| Instant::now())
+        }
+
+        fn log(&self, started: Option<Instant>, output: &O) {
+            if let (Some(log), Some(started)) = (self.logger, started) {
+                log(type_name::<I>(), started.elapsed(), output);
+            }
+        }
+
+        fn present(self, started: Option<Instant>, output: O) -> P::ViewModel
+            where P: OutPort<O>
+        {
+            self.log(started, &output);
+            self.presenter.emit(output)
+        }
+
+        /// Like `present`, handing a presenter failure back to the caller instead of assuming success.
+        fn try_present(self, started: Option<Instant>, output: O) -> Result<P::ViewModel, P::Error>
+            where P: FallibleOutPort<O>
+        {
+            self.log(started, &output);
+            self.presenter.try_emit(output)
+        }
+
+        /// Like `present`, passing `meta` through to the presenter.
+        fn present_with<M>(self, started: Option<Instant>, output: O, meta: M) -> P::ViewModel
+            where P: MetaOutPort<O, M>
+        {
+            self.log(started, &output);
+            self.presenter.emit(output, meta)
+        }
+    }
+
+    /// Marks a `ControllerBuilder` slot that has not been filled yet.
+    pub struct Missing;
+
+    /// Marks a filled `ControllerBuilder` slot.
+    pub struct Set<T>(T);
+
+    /// Builds a `_Controller` by name rather than position; `build` only exists once both parts are set.
+    pub struct ControllerBuilder<T, P> {
+        transformer: T,
+        presenter: P
+    }
+
+    impl<T, P> ControllerBuilder<T, P> {
+        pub fn transformer<T2>(self, transformer: T2) -> ControllerBuilder<Set<T2>, P> {
+            ControllerBuilder { transformer: Set(transformer), presenter: self.presenter }
+        }
+
+        pub fn presenter<P2>(self, presenter: P2) -> ControllerBuilder<T, Set<P2>> {
+            ControllerBuilder { transformer: self.transformer, presenter: Set(presenter) }
+        }
+    }
+
+    impl<T, P> ControllerBuilder<Set<T>, Set<P>> {
+        pub fn build<I, D, O>(self) -> _Controller<T, P, I, D, O> {
+            _Controller::new(self.transformer.0, self.presenter.0)
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    impl<T, P, I, D, R> _Controller<T, P, I, D, Result<R, AppError>> {
+        /// Logs the input type, elapsed time and outcome once `handle` completes.
+        pub fn with_logging(mut self) -> Self {
+            self.logger = Some(log_outcome::<R>);
+            self
+        }
+    }
+
+    impl<T, P, I, D, O> _Controller<T, P, I, D, O>
+        where T: InPort<I, Dto = D>
+    {
+        pub fn transform(self, input: I) -> Transformed<T, P, I, D, O> {
+            Transformed { started: self.started(), trans_input: self.transformer.emit(input), controller: self, _in: PhantomData, _out: PhantomData }
+        }
+    }
+
+    impl<T, P, I, D, O> _Controller<T, P, I, D, O>
+        where T: InPort<I, Dto = D>,
+              P: StreamOutPort<O>
+    {
+        /// Feeds the stream produced by `f` through the presenter chunk by chunk.
+        pub fn handle_stream<F, S>(self, input: I, f: F) -> ChunkStream<P::Chunk>
+            where F: FnOnce(D) -> S,
+                  S: Stream<Item = O> + Send + 'static
+        {
+            let stream = f(self.transformer.emit(input));
+            self.presenter.emit(Box::pin(stream))
+        }
+    }
+
+    impl<T, P, I, D, R> _Controller<T, P, I, D, Result<R, AppError>>
+        where T: FallibleInPort<I, Dto = D>,
+              P: OutPort<Result<R, AppError>>
+    {
+        pub fn transform_checked(self, input: I) -> CheckedTransformed<T, P, I, D, Result<R, AppError>> {
+            CheckedTransformed { started: self.started(), trans_input: self.transformer.emit(input), controller: self, _in: PhantomData, _out: PhantomData }
+        }
+    }
+
+    pub struct Transformed<T, P, I, D, O> {
+        controller: _Controller<T, P, I, D, O>,
+        trans_input: D,
+        started: Option<Instant>,
+        _in: PhantomData<I>,
+        _out: PhantomData<O>
+    }
+
+    impl<T, P, I, D, O> Transformed<T, P, I, D, O>
+        where T: InPort<I, Dto = D>,
+              P: OutPort<O>
+    {
+        #[cfg_attr(feature = "tracing", tracing::instrument(name = "Transformed::handle", skip_all))]
+        pub async fn handle<F, Fut>(self, f: F) -> P::ViewModel
+            where F: FnOnce(D) -> Fut,
+                  Fut: IntoFuture<Output = O>
+        {
+            let output = f(self.trans_input).await;
+            self.controller.present(self.started, output)
+        }
+
+        pub fn handle_sync<F>(self, f: F) -> P::ViewModel
+            where F: FnOnce(D) -> O
+        {
+            let output = f(self.trans_input);
+            self.controller.present(self.started, output)
+        }
+    }
+
+    impl<T, P, I, D, O> Transformed<T, P, I, D, O>
+        where T: InPort<I, Dto = D>,
+              P: FallibleOutPort<O>
+    {
+        /// For presenters that can fail; their error is returned alongside the view model type.
+        pub async fn try_handle<F, Fut>(self, f: F) -> Result<P::ViewModel, P::Error>
+            where F: FnOnce(D) -> Fut,
+                  Fut: IntoFuture<Output = O>
+        {
+            let output = f(self.trans_input).await;
+            self.controller.try_present(self.started, output)
+        }
+    }
+
+    impl<T, P, I, D, O> Transformed<T, P, I, D, O>
+        where T: InPort<I, Dto = D>
+    {
+        /// For closures that return metadata next to their output; only the output is logged.
+        pub async fn handle_with<F, Fut, M>(self, f: F) -> P::ViewModel
+            where F: FnOnce(D) -> Fut,
+                  Fut: IntoFuture<Output = (O, M)>,
+                  P: MetaOutPort<O, M>
+        {
+            let (output, meta) = f(self.trans_input).await;
+            self.controller.present_with(self.started, output, meta)
+        }
+    }
+
+    impl<T, P, I, D, R, E> Transformed<T, P, I, D, Result<R, E>>
+        where T: InPort<I, Dto = D>,
+              P: OutPort<Result<R, E>>
+    {
+        pub async fn handle_with_metrics<F, Fut>(self, f: F) -> (P::ViewModel, HandleMetrics)
+            where F: FnOnce(D) -> Fut,
+                  Fut: IntoFuture<Output = Result<R, E>>
+        {
+            let start = Instant::now();
+            let output = f(self.trans_input).await;
+            let metrics = HandleMetrics { elapsed: start.elapsed(), success: output.is_ok() };
+            (self.controller.present(self.started, output), metrics)
+        }
+    }
+
+    pub struct CheckedTransformed<T, P, I, D, O> {
+        controller: _Controller<T, P, I, D, O>,
+        trans_input: Result<D, AppError>,
+        started: Option<Instant>,
+        _in: PhantomData<I>,
+        _out: PhantomData<O>
+    }
+
+    impl<T, P, I, D, R> CheckedTransformed<T, P, I, D, Result<R, AppError>>
+        where T: FallibleInPort<I, Dto = D>,
+              P: OutPort<Result<R, AppError>>
+    {
+        /// `f` is only called when the transformer accepted the input.
+        #[cfg_attr(feature = "tracing", tracing::instrument(name = "CheckedTransformed::handle", skip_all))]
+        pub async fn handle<F, Fut>(self, f: F) -> P::ViewModel
+            where F: FnOnce(D) -> Fut,
+                  Fut: IntoFuture<Output = Result<R, AppError>>
+        {
+            let output = match self.trans_input {
+                Ok(input) => f(input).await,
+                Err(e) => Err(e)
+            };
+            self.controller.present(self.started, output)
+        }
+    }
+
+    /// Runs transform, use case and present as separate steps, so the values in between can be looked at.
+    ///
+    /// Each step returns its own type; calling them out of order does not compile.
+    pub struct Pipeline<T, P> {
+        transformer: T,
+        presenter: P
+    }
+
+    impl<T, P> Pipeline<T, P> {
+        pub fn new(transformer: T, presenter: P) -> Self {
+            Self { transformer, presenter }
+        }
+
+        pub fn input<I>(self, input: I) -> PipelineInput<T, P, I> {
+            PipelineInput { pipeline: self, input }
+        }
+    }
+
+    pub struct PipelineInput<T, P, I> {
+        pipeline: Pipeline<T, P>,
+        input: I
+    }
+
+    impl<T: InPort<I>, P, I> PipelineInput<T, P, I> {
+        pub fn transform(self) -> PipelineTransformed<P, T::Dto> {
+            let Pipeline { transformer, presenter } = self.pipeline;
+            PipelineTransformed { presenter, dto: transformer.emit(self.input) }
+        }
+    }
+
+    pub struct PipelineTransformed<P, D> {
+        presenter: P,
+        dto: D
+    }
+
+    impl<P, D> PipelineTransformed<P, D> {
+        pub fn inspect(self, f: impl FnOnce(&D)) -> Self {
+            f(&self.dto);
+            self
+        }
+
+        pub async fn execute<F, Fut>(self, f: F) -> PipelineExecuted<P, Fut::Output>
+            where F: FnOnce(D) -> Fut,
+                  Fut: IntoFuture
+        {
+            PipelineExecuted { presenter: self.presenter, output: f(self.dto).await }
+        }
+    }
+
+    pub struct PipelineExecuted<P, O> {
+        presenter: P,
+        output: O
+    }
+
+    impl<P, O> PipelineExecuted<P, O> {
+        pub fn inspect(self, f: impl FnOnce(&O)) -> Self {
+            f(&self.output);
+            self
+        }
+
+        pub fn present(self) -> P::ViewModel
+            where P: OutPort<O>
+        {
+            self.presenter.emit(self.output)
+        }
+    }
+
+    pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+    /// The remainder of the layer stack, ending in the handler closure.
+    pub struct Next<'a, N, D> {
+        inner: Box<dyn FnOnce(N) -> BoxFuture<'a, D> + Send + 'a>
+    }
+
+    impl<'a, N, D> Next<'a, N, D> {
+        fn new(inner: impl FnOnce(N) -> BoxFuture<'a, D> + Send + 'a) -> Self {
+            Self { inner: Box::new(inner) }
+        }
+
+        pub async fn run(self, input: N) -> D {
+            (self.inner)(input).await
+        }
+    }
+
+    /// Runs around the `Controller` handler closure.
+    ///
+    /// A layer may short-circuit by returning without calling `next`,
+    /// in which case its output is passed straight to the presenter.
+    #[async_trait::async_trait]
+    pub trait Layer<N, D>: 'static + Send + Sync {
+        async fn around(&self, input: N, next: Next<'_, N, D>) -> D;
+    }
+
+    /// Reports how long the rest of the stack took as a `tracing` event; without the `tracing` feature it only passes through.
+    pub struct TimingLayer;
+
+    #[async_trait::async_trait]
+    impl<N: Send + 'static, D: Send + 'static> Layer<N, D> for TimingLayer {
+        async fn around(&self, input: N, next: Next<'_, N, D>) -> D {
+            let start = Instant::now();
+            let output = next.run(input).await;
+            #[cfg(feature = "tracing")]
+            tracing::info!(target: "adaptor", elapsed = ?start.elapsed(), "handled");
+            output
+        }
+    }
+
+    /// Runs the rest of the stack inside `RequestContext::scope`.
+    pub struct ContextLayer(pub RequestContext);
+
+    #[async_trait::async_trait]
+    impl<N: Send + 'static, D: Send + 'static> Layer<N, D> for ContextLayer {
+        async fn around(&self, input: N, next: Next<'_, N, D>) -> D {
+            self.0.clone().scope(next.run(input)).await
+        }
+    }
+
+    pub struct Controller<P, D, N> {
+        presenter: P,
+        layers: Vec<Box<dyn Layer<N, D>>>,
+        logger: Option<LogFn<D>>,
+        _presenter_input: PhantomData<D>
+    }
+
+    impl<P: OutPort<D>, D, N> Controller<P, D, N> {
+        pub fn new(presenter: P) -> Self {
+            Self::with_layers(presenter, Vec::new())
+        }
+
+        /// Layers are applied outermost first.
+        pub fn with_layers(presenter: P, layers: Vec<Box<dyn Layer<N, D>>>) -> Self {
+            Self { presenter, layers, logger: None, _presenter_input: PhantomData }
+        }
+
+        pub fn capture<R: Into<N>>(self, input: R) -> Captured<R, N, D, P> {
+            let started = self.logger.map(|_| Instant::now());
+            Captured { controller: self, input, started, _need: PhantomData, _conv: PhantomData }
+        }
+
+        /// Like `capture`, but leaves `input` with the caller.
+        pub fn capture_ref<'a, R>(self, input: &'a R) -> Captured<&'a R, N, D, P>
+            where &'a R: Into<N>
+        {
+            self.capture(input)
+        }
+
+        /// Like `capture`, for a batch handled item by item.
+        pub fn capture_many<R: Into<N>>(self, inputs: Vec<R>) -> CapturedMany<R, N, D, P> {
+            CapturedMany { controller: self, inputs, max_concurrency: 1 }
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    impl<P: OutPort<Result<R, AppError>>, R, N> Controller<P, Result<R, AppError>, N> {
+        /// Logs the input type, elapsed time and outcome once `handle` completes.
+        pub fn with_logging(mut self) -> Self {
+            self.logger = Some(log_outcome::<R>);
+            self
+        }
+    }
+
+    impl<P: OutPort<Result<T, AppError>>, T, N> Controller<P, Result<T, AppError>, N> {
+        /// Like `capture`, for inputs whose conversion can fail.
+        ///
+        /// The conversion runs here; if it fails, `handle` presents the error without running the layers or the closure.
+        pub fn try_capture<R>(self, input: R) -> TryCaptured<N, Result<T, AppError>, P>
+            where R: TryInto<N>,
+                  R::Error: Into<AppError>
+        {
+            match input.try_into() {
+                Ok(input) => TryCaptured(Ok(self.capture(input))),
+                Err(e) => TryCaptured(Err((self.presenter, e.into())))
+            }
+        }
+    }
+
+    pub struct Captured<R, N, D, P> {
+        controller: Controller<P, D, N>,
+        input: R,
+        started: Option<Instant>,
+        _need: PhantomData<N>,
+        _conv: PhantomData<D>
+    }
+    
+    impl<R, N, D, P> Captured<R, N, D, P>
+        where R: Into<N>,
+              N: Send + 'static,
+              D: Send + 'static,
+              P: OutPort<D>
+    {
+        #[cfg_attr(feature = "tracing", tracing::instrument(name = "Captured::handle", skip_all))]
+        pub async fn handle<F, Fut>(self, f: F) -> P::ViewModel
+            where F: Fn(N) -> Fut + Send + Sync,
+                  Fut: IntoFuture<Output = D>,
+                  Fut::IntoFuture: Send
+        {
+            let (presenter, output) = self.execute(f).await;
+            presenter.emit(output)
+        }
+
+        /// Runs `f` inside the layer stack and hands back the presenter with its input.
+        async fn execute<F, Fut>(self, f: F) -> (P, D)
+            where F: Fn(N) -> Fut + Send + Sync,
+                  Fut: IntoFuture<Output = D>,
+                  Fut::IntoFuture: Send
+        {
+            let Controller { presenter, layers, logger, .. } = self.controller;
+            let output = run_layers(&layers, &f, self.input.into()).await;
+            if let (Some(log), Some(started)) = (logger, self.started) {
+                log(type_name::<R>(), started.elapsed(), &output);
+            }
+            (presenter, output)
+        }
+    }
+
+    /// Wraps `f` in `layers`, outermost first, and runs it on `input`.
+    async fn run_layers<N, D, F, Fut>(layers: &[Box<dyn Layer<N, D>>], f: &F, input: N) -> D
+        where N: Send + 'static,
+              D: Send + 'static,
+              F: Fn(N) -> Fut + Send + Sync,
+              Fut: IntoFuture<Output = D>,
+              Fut::IntoFuture: Send
+    {
+        let mut next = Next::new(move |input| -> BoxFuture<'_, D> {
+            Box::pin(f(input).into_future())
+        });
+        for layer in layers.iter().rev() {
+            let inner = next;
+            next = Next::new(move |input| layer.around(input, inner));
+        }
+        next.run(input).await
+    }
+
+    pub struct CapturedMany<R, N, D, P> {
+        controller: Controller<P, D, N>,
+        inputs: Vec<R>,
+        max_concurrency: usize
+    }
+
+    impl<R, N, D, P> CapturedMany<R, N, D, P> {
+        /// Lets up to `max_concurrency` items run at once; the default of `1` runs them in turn.
+        pub fn max_concurrency(mut self, max_concurrency: usize) -> Self {
+            self.max_concurrency = max_concurrency.max(1);
+            self
+        }
+    }
+
+    impl<R, N, D, P> CapturedMany<R, N, D, P>
+        where R: Into<N>,
+              N: Send + 'static,
+              D: Send + 'static,
+              P: OutPort<D>
+    {
+        /// Runs each input through the layers and `f`, returning one view model per input, in input order.
+        ///
+        /// An item's error is only presented; it does not stop the rest of the batch.
+        pub async fn handle<F, Fut>(self, f: F) -> Vec<P::ViewModel>
+            where F: Fn(N) -> Fut + Send + Sync,
+                  Fut: IntoFuture<Output = D>,
+                  Fut::IntoFuture: Send
+        {
+            let Controller { presenter, layers, logger, .. } = self.controller;
+            let permits = Semaphore::new(self.max_concurrency);
+            let (layers, f, permits) = (&layers, &f, &permits);
+            let outputs = future::join_all(self.inputs.into_iter().map(|input| async move {
+                let _permit = permits.acquire().await.expect("semaphore is never closed");
+                let started = logger.map(|_| Instant::now());
+                let output = run_layers(layers, f, input.into()).await;
+                if let (Some(log), Some(started)) = (logger, started) {
+                    log(type_name::<R>(), started.elapsed(), &output);
+                }
+                output
+            })).await;
+            outputs.into_iter().map(|output| presenter.emit(output)).collect()
+        }
+    }
+
+    impl<R, N, T, E, P> Captured<R, N, Result<T, E>, P>
+        where R: Into<N>,
+              N: Send + 'static,
+              T: Send + 'static,
+              E: Send + 'static,
+              P: OutPort<Result<T, E>>
+    {
+        pub async fn handle_with_metrics<F, Fut>(self, f: F) -> (P::ViewModel, HandleMetrics)
+            where F: Fn(N) -> Fut + Send + Sync,
+                  Fut: IntoFuture<Output = Result<T, E>>,
+                  Fut::IntoFuture: Send
+        {
+            let start = Instant::now();
+            let (presenter, output) = self.execute(f).await;
+            let metrics = HandleMetrics { elapsed: start.elapsed(), success: output.is_ok() };
+            (presenter.emit(output), metrics)
+        }
+
+        /// A token that is already cancelled skips `f` entirely; one cancelled mid-flight drops it.
+        pub async fn handle_cancellable<F, Fut>(self, cancel: Option<&CancellationToken>, f: F) -> P::ViewModel
+            where F: Fn(N) -> Fut + Send + Sync,
+                  Fut: IntoFuture<Output = Result<T, E>>,
+                  Fut::IntoFuture: Send,
+                  E: From<KernelError>
+        {
+            let Some(cancel) = cancel else {
+                return self.handle(f).await;
+            };
+            let f = &f;
+            self.handle(move |input| async move {
+                if cancel.is_cancelled() {
+                    return Err(KernelError::Cancelled.into());
+                }
+                tokio::select! {
+                    biased;
+                    _ = cancel.cancelled() => Err(KernelError::Cancelled.into()),
+                    res = f(input).into_future() => res,
+                }
+            }).await
+        }
+    }
+
+    impl<R, N, T, P> Captured<R, N, Result<T, AppError>, P>
+        where R: Into<N>,
+              N: Send + 'static,
+              T: Send + 'static,
+              P: OutPort<Result<T, AppError>>
+    {
+        /// For closures with their own error type; `Err` goes straight to the presenter's error branch.
+        pub async fn handle_try<F, Fut, E>(self, f: F) -> P::ViewModel
+            where F: Fn(N) -> Fut + Send + Sync,
+                  Fut: IntoFuture<Output = Result<T, E>>,
+                  Fut::IntoFuture: Send,
+                  E: Into<AppError>
+        {
+            let f = &f;
+            self.handle(move |input| {
+                let fut = f(input).into_future();
+                async move { fut.await.map_err(Into::into) }
+            }).await
+        }
+    }
+
+    pub struct TryCaptured<N, D, P>(Result<Captured<N, N, D, P>, (P, AppError)>);
+
+    impl<N, T, P> TryCaptured<N, Result<T, AppError>, P>
+        where N: Send + 'static,
+              T: Send + 'static,
+              P: OutPort<Result<T, AppError>>
+    {
+        pub async fn handle<F, Fut>(self, f: F) -> P::ViewModel
+            where F: Fn(N) -> Fut + Send + Sync,
+                  Fut: IntoFuture<Output = Result<T, AppError>>,
+                  Fut::IntoFuture: Send
+        {
+            match self.0 {
+                Ok(captured) => captured.handle(f).await,
+                Err((presenter, e)) => presenter.emit(Err(e))
+            }
+        }
+    }
+
+    impl<P: OutPort<D>, D, A, B> Controller<P, D, (A, B)> {
+        /// Layers observe the two inputs as a tuple.
+        pub fn capture2<RA: Into<A>, RB: Into<B>>(self, a: RA, b: RB) -> Captured2<RA, RB, A, B, D, P> {
+            Captured2 { controller: self, a, b }
+        }
+    }
+
+    pub struct Captured2<RA, RB, A, B, D, P> {
+        controller: Controller<P, D, (A, B)>,
+        a: RA,
+        b: RB
+    }
+
+    impl<RA, RB, A, B, D, P> Captured2<RA, RB, A, B, D, P>
+        where RA: Into<A>,
+              RB: Into<B>,
+              A: Send + 'static,
+              B: Send + 'static,
+              D: Send + 'static,
+              P: OutPort<D>
+    {
+        pub async fn handle<F, Fut>(self, f: F) -> P::ViewModel
+            where F: Fn(A, B) -> Fut + Send + Sync,
+                  Fut: IntoFuture<Output = D>,
+                  Fut::IntoFuture: Send
+        {
+            self.controller
+                .capture((self.a.into(), self.b.into()))
+                .handle(move |(a, b)| f(a, b))
+                .await
+        }
+    }
+
+    /// Drives either controller shape with one call, for code generic over both.
+    #[async_trait::async_trait]
+    pub trait ControllerLike<I>: Sized {
+        /// What the handler closure receives.
+        type Input;
+        /// What the handler closure returns.
+        type Output;
+        type ViewModel;
+
+        async fn run<F, Fut>(self, input: I, f: F) -> Self::ViewModel
+            where F: Fn(Self::Input) -> Fut + Send + Sync,
+                  Fut: Future<Output = Self::Output> + Send;
+    }
+
+    #[async_trait::async_trait]
+    impl<T, P, I, D, O> ControllerLike<I> for _Controller<T, P, I, D, O>
+        where T: InPort<I, Dto = D>,
+              P: OutPort<O>,
+              I: Send + 'static,
+              D: Send + 'static,
+              O: Send + 'static
+    {
+        type Input = D;
+        type Output = O;
+        type ViewModel = P::ViewModel;
+
+        async fn run<F, Fut>(self, input: I, f: F) -> Self::ViewModel
+            where F: Fn(Self::Input) -> Fut + Send + Sync,
+                  Fut: Future<Output = Self::Output> + Send
+        {
+            self.transform(input).handle(f).await
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl<P, D, N, R> ControllerLike<R> for Controller<P, D, N>
+        where P: OutPort<D>,
+              D: Send + 'static,
+              N: Send + 'static,
+              R: Into<N> + Send + 'static
+    {
+        type Input = N;
+        type Output = D;
+        type ViewModel = P::ViewModel;
+
+        async fn run<F, Fut>(self, input: R, f: F) -> Self::ViewModel
+            where F: Fn(Self::Input) -> Fut + Send + Sync,
+                  Fut: Future<Output = Self::Output> + Send
+        {
+            self.capture(input).handle(f).await
+        }
+    }
+}
+
+/// Glue for web frameworks
+#[cfg(feature = "axum")]
+pub mod integration {
+    pub mod axum {
+        use std::sync::Arc;
+
+        use ::axum::{
+            extract::State,
+            http::{header, StatusCode},
+            response::{IntoResponse, Response},
+            Json,
+        };
+
+        use crate::{
+            adaptor::{Controller, HttpPresenter},
+            application::{CreateDataService, DataDto, DependOnCreateDataService},
+            inject::Handler,
+        };
+
+        /// Turns the `(status, body)` view model of an `HttpOutPort` into a JSON response.
+        pub struct HttpResponse(pub (u16, String));
+
+        impl IntoResponse for HttpResponse {
+            fn into_response(self) -> Response {
+                let (status, body) = self.0;
+                let status = StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+                (status, [(header::CONTENT_TYPE, "application/json")], body).into_response()
+            }
+        }
+
+        /// `POST` handler for the create flow.
+        pub async fn create_data(State(handler): State<Arc<Handler>>, Json(input): Json<DataDto>) -> HttpResponse {
+            let res = Controller::new(HttpPresenter::new())
+                .capture(input)
+                .handle(|input| async {
+                    handler.create_simple_data_service()
+                        .create(input)
+                        .await
+                }).await;
+            HttpResponse(res)
+        }
+    }
+}
+
+/// Everything needed to write a handler, in one import.
+pub mod prelude {
+    pub use crate::{
+        kernel::{Repository, DependOnRepository},
+        application::{CreateDataService, DependOnCreateDataService, DataDto, AppError},
+        adaptor::{InPort, OutPort, Controller},
+    };
+}
+
+use std::future::IntoFuture;
+
+use adaptor::{_Controller as ControllerA, Controller as ControllerB, ContextLayer, InPort, FallibleInPort, MetaOutPort, Pipeline, PresenterA, PresenterB, TimingLayer};
+use application::{DependOnCreateDataService, CreateDataService, DependOnUpdateDataService, UpdateDataService};
+use kernel::{DataId, RequestContext};
+use inject::Handler;
+
+use crate::application::{DataDto, AppError};
+
+#[derive(Clone)]
+pub struct UserInputForm {
+    pub id: String,
+    pub name: String
+}
+
+#[derive(Default)]
+pub struct TransformerA;
+
+impl InPort<UserInputForm> for TransformerA {
+    type Dto = DataDto;
+    fn emit(&self, input: UserInputForm) -> Self::Dto {
+        Self::Dto::builder()
+            .id(input.id)
+            .name(input.name)
+            .build()
+            .expect("both fields are always set")
+    }
+}
+
+pub struct TrimmingTransformer;
+
+impl InPort<UserInputForm> for TrimmingTransformer {
+    type Dto = UserInputForm;
+    fn emit(&self, input: UserInputForm) -> Self::Dto {
+        UserInputForm { name: input.name.trim().to_string(), ..input }
+    }
+}
+
+pub struct ValidatingTransformer;
+
+impl FallibleInPort<UserInputForm> for ValidatingTransformer {
+    type Dto = DataDto;
+    fn emit(&self, input: UserInputForm) -> Result<Self::Dto, AppError> {
+        if input.name.is_empty() {
+            return Err(AppError::Validation("name must not be empty".to_string()));
+        }
+        Self::Dto::builder()
+            .id(input.id)
+            .name(input.name)
+            .build()
+    }
+}
+
+impl TryFrom<UserInputForm> for DataDto {
+    type Error = AppError;
+    fn try_from(value: UserInputForm) -> Result<Self, Self::Error> {
+        if value.name.trim().is_empty() {
+            return Err(AppError::Validation("name must not be blank".to_string()));
+        }
+        Self::builder()
+            .id(value.id)
+            .name(value.name)
+            .build()
+    }
+}
+
+impl TryFrom<&UserInputForm> for DataDto {
+    type Error = AppError;
+    fn try_from(value: &UserInputForm) -> Result<Self, Self::Error> {
+        value.clone().try_into()
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let handler = Handler::init();
+
+    pub struct CacheAwarePresenter;
+
+    impl MetaOutPort<Result<DataDto, AppError>, bool> for CacheAwarePresenter {
+        type ViewModel = Result<(String, bool), AppError>;
+        fn emit(&self, input: Result<DataDto, AppError>, cache_hit: bool) -> Self::ViewModel {
+            input.map(|dto| (dto.id, cache_hit))
+        }
+    }
+
+    let input = UserInputForm {
+        id: "abc123".to_string(),
+        name: "test man".to_string()
+    };
+
+    let res = ControllerA::new(TransformerA, PresenterA)
+        .transform(input.clone())
+        .handle(|input| async { // <- ここで型が推論される
+            handler.create_simple_data_service()
+                .create(input)
+                .await
+        }).await;
+    println!("{:?}", res);
+
+    let controller = ControllerA::new(TransformerA, PresenterB);
+    #[cfg(feature = "tracing")]
+    let controller = controller.with_logging();
+    let res = controller
+        .transform(input.clone())
+        .handle(|input| async {
+            handler.create_simple_data_service()
+                .create(input)
+                .await
+        }).await;
+    println!("{:?}", res);
+
+    let res = ControllerA::new(TrimmingTransformer.then(TransformerA), PresenterA)
+        .transform(UserInputForm { name: "  test man  ".to_string(), ..input.clone() })
+        .handle(|input| async {
+            handler.create_simple_data_service()
+                .create(input)
+                .await
+        }).await;
+    println!("{:?}", res);
+
+    let res = ControllerA::new(TransformerA, CacheAwarePresenter)
+        .transform(input.clone())
+        .handle_with(|input| async {
+            let res = handler.create_simple_data_service()
+                .create(input)
+                .await;
+            (res, true)
+        }).await;
+    println!("{:?}", res);
+
+    let res = ControllerA::new(ValidatingTransformer, PresenterA)
+        .transform_checked(UserInputForm { name: String::new(), ..input.clone() })
+        .handle(|input| async {
+            handler.create_simple_data_service()
+                .create(input)
+                .await
+        }).await;
+    println!("{:?}", res);
+
+    let res = ControllerB::new(PresenterA)
+        .try_capture(input.clone())
+        .handle(|input| async {
+            handler.create_simple_data_service()
+                .create(input) // <- ここで型が推論される
+                .await
+        }).await;
+    println!("{:?}", res);
+
+    let res = ControllerB::new(PresenterA)
+        .try_capture(UserInputForm { name: String::new(), ..input.clone() })
+        .handle(|input| async {
+            handler.create_simple_data_service()
+                .create(input) // <- 変換に失敗したのでここは呼ばれない
+                .await
+        }).await;
+    println!("{:?}", res);
+    
+    let res = ControllerB::new(PresenterA)
+        .capture2(input.id.parse::<DataId>()?, DataDto::try_from(input.clone())?)
+        .handle(|id: DataId, patch: DataDto| {
+            let input = DataDto { id: id.into(), ..patch };
+            async {
+                handler.update_data_service()
+                    .update(input)
+                    .await
+            }
+        }).await;
+    println!("{:?}", res);
+
+    let res = ControllerB::new(PresenterA)
+        .try_capture(&input)
+        .handle(|input| async {
+            handler.create_simple_data_service()
+                .create(input)
+                .await
+        }).await;
+    println!("{:?} <- {}", res, input.id);
+
+    let res = ControllerB::new(PresenterA)
+        .capture_many(vec![
+            TransformerA.emit(input.clone()),
+            TransformerA.emit(UserInputForm { name: String::new(), ..input.clone() }),
+            TransformerA.emit(UserInputForm { id: "def456".to_string(), ..input.clone() }),
+        ])
+        .max_concurrency(2)
+        .handle(|input| async {
+            handler.create_simple_data_service()
+                .create(input)
+                .await
+        }).await;
+    println!("{:?}", res);
+
+    let res = Pipeline::new(TransformerA, PresenterA)
+        .input(input.clone())
+        .transform()
+        .inspect(|dto| println!("transformed: {:?}", dto))
+        .execute(|input| async {
+            handler.create_simple_data_service()
+                .create(input)
+                .await
+        }).await
+        .present();
+    println!("{:?}", res);
+
+    let context = RequestContext::new("req-1").with_tenant("tenant-a");
+    let res = ControllerB::with_layers(PresenterA, vec![Box::new(ContextLayer(context))])
+        .try_capture(input.clone())
+        .handle(|input| async {
+            handler.create_simple_data_service()
+                .create(input)
+                .await
+        }).await;
+    println!("{:?}", res);
+
+    let res = ControllerB::with_layers(PresenterB, vec![Box::new(TimingLayer)])
+        .try_capture(input)
+        .handle(|input| async {
+            handler.create_simple_data_service()
+                .create(input)
+                .await  
+        }).await;
+    println!("{:?}", res);
+
+    Ok(())
+}
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        mem,
+        sync::{Arc, Mutex, atomic::{AtomicBool, AtomicU32, Ordering}},
+    };
+
+    use tokio::sync::Notify;
+
+    use time::{Duration, OffsetDateTime};
+
+    use crate::{
+        application::*,
+        driver::*,
+        kernel::{*, error::KernelError},
+    };
+
+    /// A container wired to whatever repository, audit log, clock and publisher a test needs.
+    struct Container<R, A = NoopAuditLog, C = SystemClock, E = NoopEventPublisher> {
+        repo: R,
+        audit: A,
+        clock: C,
+        publisher: E,
+        ids: SequenceGenerator
+    }
+
+    impl<R: Repository> Container<R> {
+        fn new(repo: R) -> Self {
+            Self { repo, audit: NoopAuditLog, clock: SystemClock, publisher: NoopEventPublisher, ids: SequenceGenerator::new("id-") }
+        }
+    }
+
+    impl<R, A, C, E> Container<R, A, C, E> {
+        fn with_audit<A2>(self, audit: A2) -> Container<R, A2, C, E> {
+            Container { repo: self.repo, audit, clock: self.clock, publisher: self.publisher, ids: self.ids }
+        }
+
+        fn with_clock<C2>(self, clock: C2) -> Container<R, A, C2, E> {
+            Container { repo: self.repo, audit: self.audit, clock, publisher: self.publisher, ids: self.ids }
+        }
+
+        fn with_publisher<E2>(self, publisher: E2) -> Container<R, A, C, E2> {
+            Container { repo: self.repo, audit: self.audit, clock: self.clock, publisher, ids: self.ids }
+        }
+    }
+
+    impl<R: Repository, A: AuditLog, C: Clock, E: EventPublisher> DependOnRepository for Container<R, A, C, E> {
+        type Repository = R;
+        fn repository(&self) -> &R {
+            &self.repo
+        }
+    }
+
+    impl<R: Repository, A: AuditLog, C: Clock, E: EventPublisher> DependOnEventPublisher for Container<R, A, C, E> {
+        type EventPublisher = E;
+        fn event_publisher(&self) -> &E {
+            &self.publisher
+        }
+    }
+
+    impl<R: Repository, A: AuditLog, C: Clock, E: EventPublisher> DependOnIdGenerator for Container<R, A, C, E> {
+        type IdGenerator = SequenceGenerator;
+        fn id_generator(&self) -> &SequenceGenerator {
+            &self.ids
+        }
+    }
+
+    impl<R: Repository, A: AuditLog, C: Clock, E: EventPublisher> DependOnAuditLog for Container<R, A, C, E> {
+        type AuditLog = A;
+        fn audit_log(&self) -> &A {
+            &self.audit
+        }
+    }
+
+    impl<R: Repository, A: AuditLog, C: Clock, E: EventPublisher> DependOnClock for Container<R, A, C, E> {
+        type Clock = C;
+        fn clock(&self) -> &C {
+            &self.clock
+        }
+    }
+
+    impl<R: Repository, A: AuditLog, C: Clock, E: EventPublisher> CreateDataService for Container<R, A, C, E> {}
+    impl<R: Repository, A: AuditLog, C: Clock, E: EventPublisher> BatchCreateDataService for Container<R, A, C, E> {}
+    impl<R: Repository, A: AuditLog, C: Clock, E: EventPublisher> ListDataService for Container<R, A, C, E> {}
+
+    /// An in-memory store with knobs for injecting failures and lining up races.
+    #[derive(Default)]
+    struct ProbeRepository {
+        inner: InMemoryRepository,
+        /// Parks the next `find_by_id` after it has read, until `release` is notified.
+        park_reads: AtomicBool,
+        read: Notify,
+        release: Notify,
+        /// Parked reads whose future was dropped before `release`.
+        abandoned_reads: Arc<AtomicU32>,
+        fail_begin: AtomicBool,
+        /// How many of the next `create` calls fail with `Driver`.
+        create_failures: AtomicU32,
+        create_attempts: AtomicU32,
+        bulk_creates: AtomicU32,
+        find_calls: AtomicU32,
+        /// The `RequestContext` each `create` ran under.
+        create_contexts: Mutex<Vec<Option<RequestContext>>>,
+        /// `commit` and `rollback` of every transaction, in order.
+        events: Arc<Mutex<Vec<&'static str>>>,
+        /// Replaces `created_at` in `create_returning`, like a column default would.
+        stamp: Option<OffsetDateTime>
+    }
+
+    impl ProbeRepository {
+        fn parked() -> Self {
+            Self { park_reads: true.into(), ..Self::default() }
+        }
+
+        fn failing_begin() -> Self {
+            Self { fail_begin: true.into(), ..Self::default() }
+        }
+
+        fn failing_creates(failures: u32) -> Self {
+            Self { create_failures: failures.into(), ..Self::default() }
+        }
+
+        fn stamping(at: OffsetDateTime) -> Self {
+            Self { stamp: Some(at), ..Self::default() }
+        }
+
+        fn events(&self) -> Vec<&'static str> {
+            self.events.lock().unwrap().clone()
+        }
+    }
+
+    /// Counts itself into the shared total if dropped instead of forgotten.
+    struct Abandoned(Arc<AtomicU32>);
+
+    impl Drop for Abandoned {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    struct ProbeTransaction {
+        inner: InMemoryTransaction,
+        events: Arc<Mutex<Vec<&'static str>>>
+    }
+
+    #[async_trait::async_trait]
+    impl Transaction for ProbeTransaction {
+        async fn commit(self) -> Result<(), KernelError> {
+            self.events.lock().unwrap().push("commit");
+            self.inner.commit().await
+        }
+
+        async fn rollback(self) -> Result<(), KernelError> {
+            self.events.lock().unwrap().push("rollback");
+            self.inner.rollback().await
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Repository for ProbeRepository {
+        type Tx = ProbeTransaction;
+
+        async fn begin(&self) -> Result<Self::Tx, KernelError> {
+            if self.fail_begin.load(Ordering::SeqCst) {
+                return Err(KernelError::Driver("begin refused".to_string()));
+            }
+            Ok(ProbeTransaction { inner: self.inner.begin().await?, events: Arc::clone(&self.events) })
+        }
+
+        async fn create(&self, data: &Data) -> Result<(), KernelError> {
+            self.create_attempts.fetch_add(1, Ordering::SeqCst);
+            self.create_contexts.lock().unwrap().push(RequestContext::current());
+            let failing = self.create_failures.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
+            if failing.is_ok() {
+                return Err(KernelError::Driver("create refused".to_string()));
+            }
+            self.inner.create(data).await
+        }
+
+        /// Counts the call, then creates row by row so that `failing_creates` still applies.
+        async fn create_many_returning(&self, data: &[Data]) -> Result<Vec<Result<Data, KernelError>>, KernelError> {
+            self.bulk_creates.fetch_add(1, Ordering::SeqCst);
+            let mut results = Vec::with_capacity(data.len());
+            for data in data {
+                results.push(self.create_returning(data).await);
+            }
+            Ok(results)
+        }
+
+        async fn create_returning(&self, data: &Data) -> Result<Data, KernelError> {
+            let stored = match self.stamp {
+                Some(at) => Data::try_new(data.id().clone(), data.name().as_ref(), &FixedClock(at))?
+                    .with_version(data.version())
+                    .with_extra(data.extra().clone()),
+                None => data.clone()
+            };
+            self.create(&stored).await?;
+            Ok(stored)
+        }
+
+        async fn create_in(&self, tx: &mut Self::Tx, data: &Data) -> Result<(), KernelError> {
+            self.inner.create_in(&mut tx.inner, data).await
+        }
+
+        async fn find_by_id(&self, id: &str) -> Result<Option<Data>, KernelError> {
+            self.find_calls.fetch_add(1, Ordering::SeqCst);
+            let found = self.inner.find_by_id(id).await;
+            if self.park_reads.swap(false, Ordering::SeqCst) {
+                let abandoned = Abandoned(Arc::clone(&self.abandoned_reads));
+                self.read.notify_one();
+                self.release.notified().await;
+                mem::forget(abandoned);
+            }
+            found
+        }
+
+        async fn update(&self, data: &Data, expected_version: u64) -> Result<bool, KernelError> {
+            self.inner.update(data, expected_version).await
+        }
+
+        async fn update_returning(&self, data: &Data, expected_version: u64) -> Result<Option<Data>, KernelError> {
+            self.inner.update_returning(data, expected_version).await
+        }
+
+        async fn delete(&self, id: &str) -> Result<bool, KernelError> {
+            self.inner.delete(id).await
+        }
+
+        async fn list(&self, offset: u64, limit: u64) -> Result<Vec<Data>, KernelError> {
+            self.inner.list(offset, limit).await
+        }
+
+        async fn count(&self) -> Result<u64, KernelError> {
+            self.inner.count().await
+        }
+
+        async fn find_by_name_prefix(&self, prefix: &str, limit: u64) -> Result<Vec<Data>, KernelError> {
+            self.inner.find_by_name_prefix(prefix, limit).await
+        }
+
+        fn stream_all(&self) -> DataStream {
+            self.inner.stream_all()
+        }
+    }
+
+    /// Passes a `DataDto` through unchanged.
+    struct Identity;
+
+    impl crate::adaptor::InPort<DataDto> for Identity {
+        type Dto = DataDto;
+        fn emit(&self, input: DataDto) -> DataDto {
+            input
+        }
+    }
+
+    fn dto(id: &str, name: &str) -> DataDto {
+        DataDto { id: id.to_string(), name: name.to_string(), created_at: None, version: 0, attributes: BTreeMap::new() }
+    }
+
+    #[tokio::test]
+    async fn update_keeps_the_stored_created_at() {
+        let epoch = FixedClock(OffsetDateTime::UNIX_EPOCH);
+        let later = FixedClock(OffsetDateTime::UNIX_EPOCH + Duration::days(1));
+        let repo = InMemoryRepository::new();
+        repo.create(&Data::try_new("a".parse().unwrap(), "old", &epoch).unwrap()).await.unwrap();
+
+        let container = Container::new(repo).with_clock(later);
+        let updated = container.update(dto("a", "new")).await.unwrap();
+
+        assert_eq!(updated.created_at, Some(epoch.0));
+        assert_eq!(updated.name, "new");
+        assert_eq!(updated.version, 1);
+        let stored = container.repo.find_by_id("a").await.unwrap().unwrap();
+        assert_eq!(stored.created_at(), epoch.0);
+    }
+
+    #[tokio::test]
+    async fn a_read_racing_an_update_does_not_cache_the_stale_value() {
+        let repo = ProbeRepository::parked();
+        repo.inner.create(&Data::new("a", "old")).await.unwrap();
+        let cache = Arc::new(CachingRepository::new(repo, 4));
+
+        let reader = tokio::spawn({
+            let cache = Arc::clone(&cache);
+            async move { cache.find_by_id("a").await }
+        });
+        cache.inner().read.notified().await;
+        cache.update(&Data::new("a", "new"), 0).await.unwrap();
+        cache.inner().release.notify_one();
+
+        assert_eq!(reader.await.unwrap().unwrap().unwrap().name().as_ref(), "old");
+        assert_eq!(cache.find_by_id("a").await.unwrap().unwrap().name().as_ref(), "new");
+    }
+
+    #[tokio::test]
+    async fn pool_and_transaction_messages_go_to_the_repository_sink() {
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let repo = DataRepository::new(Pool::in_memory()).with_sink({
+            let messages = Arc::clone(&messages);
+            move |message: &str| messages.lock().unwrap().push(message.to_string())
+        });
+
+        let tx = repo.begin().await.unwrap();
+        tx.commit().await.unwrap();
+
+        assert_eq!(*messages.lock().unwrap(), vec!["begin".to_string(), "commit []".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn ping_fails_once_the_pool_is_closed() {
+        let pool = Pool::in_memory();
+        pool.ping().await.unwrap();
+        pool.close().await;
+        assert!(matches!(pool.ping().await, Err(KernelError::Unavailable)));
+    }
+
+    #[tokio::test]
+    async fn soft_deletes_are_stamped_by_the_repository_clock() {
+        let clock = FixedClock(OffsetDateTime::UNIX_EPOCH);
+        let repo = InMemoryRepository::with_soft_delete().with_clock(clock);
+        repo.create(&Data::new("a", "A")).await.unwrap();
+        repo.create(&Data::new("b", "B")).await.unwrap();
+
+        repo.delete("a").await.unwrap();
+        repo.delete_many(&["b".to_string()]).await.unwrap();
+
+        for id in ["a", "b"] {
+            let data = repo.find_by_id_including_deleted(id).await.unwrap().unwrap();
+            assert_eq!(data.deleted_at(), Some(clock.0));
+        }
+    }
+
+    #[tokio::test]
+    async fn a_failed_secondary_begin_rolls_the_primary_back() {
+        let repo = DualWriteRepository::new(ProbeRepository::default(), ProbeRepository::failing_begin(), DualWritePolicy::FailFast);
+        assert!(matches!(repo.begin().await, Err(KernelError::Driver(_))));
+        assert_eq!(repo.primary().events(), vec!["rollback"]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn enveloped_errors_carry_the_error_code() {
+        use crate::adaptor::{JsonPresenter, OutPort};
+
+        let body = JsonPresenter::new(true).emit(Err(AppError::NotFound)).unwrap();
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["error"], 404);
+        assert_eq!(body["message"], AppError::NotFound.to_string());
+    }
+
+    #[test]
+    fn records_with_different_attributes_are_not_equal() {
+        let plain = Data::new("a", "A");
+        let tagged = plain.clone().with_extra([("k".to_string(), "v".to_string())].into());
+        assert_ne!(plain, tagged);
+    }
+
+    #[tokio::test]
+    async fn a_conflicting_row_only_fails_itself() {
+        let container = Container::new(InMemoryRepository::new());
+        container.repo.create(&Data::new("b", "existing")).await.unwrap();
+
+        let results = container.create_many(vec![dto("a", "A"), dto("b", "B"), dto("c", "C")]).await.unwrap();
+
+        assert!(results[0].is_ok());
+        assert!(matches!(results[1], Err(AppError::Conflict)));
+        assert!(results[2].is_ok());
+        assert_eq!(container.repo.count().await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn a_batch_sends_only_its_valid_rows_in_one_bulk_call() {
+        let container = Container::new(ProbeRepository::default());
+
+        let results = container.create_many(vec![dto("a", "A"), dto("b", " "), dto("c", "C")]).await.unwrap();
+
+        assert!(matches!(results[1], Err(AppError::Validation(_))));
+        assert_eq!(container.repo.bulk_creates.load(Ordering::SeqCst), 1);
+        assert_eq!(container.repo.create_attempts.load(Ordering::SeqCst), 2);
+    }
+
+    #[cfg(feature = "testing")]
+    mod audit {
+        use super::*;
+        use crate::testing::InMemoryAuditLog;
+
+        fn audited() -> Container<InMemoryRepository, InMemoryAuditLog> {
+            Container::new(InMemoryRepository::new()).with_audit(InMemoryAuditLog::new())
+        }
+
+        #[tokio::test]
+        async fn deleting_an_unknown_id_is_not_audited() {
+            let container = audited();
+            container.delete("nope".to_string()).await.unwrap();
+            assert!(container.audit.entries().is_empty());
+        }
+
+        #[tokio::test]
+        async fn a_delete_is_audited_without_reading_the_record_first() {
+            let container = Container::new(ProbeRepository::default()).with_audit(InMemoryAuditLog::new());
+            container.repo.create(&Data::new("a", "A")).await.unwrap();
+
+            container.delete("a".to_string()).await.unwrap();
+
+            assert_eq!(container.audit.entries(), vec![(AuditAction::Deleted, "a".to_string())]);
+            assert_eq!(container.repo.find_calls.load(Ordering::SeqCst), 0);
+        }
+
+        #[tokio::test]
+        async fn bulk_delete_audits_each_deleted_id() {
+            let container = audited();
+            container.repo.create(&Data::new("a", "A")).await.unwrap();
+
+            let deleted = container.delete_many(vec!["a".to_string(), "nope".to_string()]).await.unwrap();
+
+            assert_eq!(deleted, 1);
+            assert_eq!(container.audit.entries(), vec![(AuditAction::Deleted, "a".to_string())]);
+        }
+
+        #[tokio::test]
+        async fn upsert_audits_created_then_updated() {
+            let container = audited();
+            container.upsert(dto("a", "A")).await.unwrap();
+            container.upsert(dto("a", "B")).await.unwrap();
+            assert_eq!(container.audit.entries(), vec![
+                (AuditAction::Created, "a".to_string()),
+                (AuditAction::Updated, "a".to_string()),
+            ]);
+        }
+
+        #[tokio::test]
+        async fn batch_create_audits_each_created_row() {
+            let container = audited();
+            let results = container.create_many(vec![dto("a", "A"), dto("b", " "), dto("c", "C")]).await.unwrap();
+            assert!(results[1].is_err());
+            assert_eq!(container.audit.entries(), vec![
+                (AuditAction::Created, "a".to_string()),
+                (AuditAction::Created, "c".to_string()),
+            ]);
+        }
+
+        #[tokio::test]
+        async fn a_create_is_audited_exactly_once() {
+            let container = audited();
+            container.create(dto("a", "A")).await.unwrap();
+            assert_eq!(container.audit.entries(), vec![(AuditAction::Created, "a".to_string())]);
+        }
+
+        #[tokio::test]
+        async fn a_failed_create_is_not_audited() {
+            let container = audited();
+            container.repo.create(&Data::new("a", "existing")).await.unwrap();
+            assert!(container.create(dto("a", "A")).await.is_err());
+            assert!(container.audit.entries().is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn update_round_trips_the_input_fields() {
+        let container = Container::new(InMemoryRepository::new());
+        container.create(dto("a", "old")).await.unwrap();
+
+        let input = DataDto { attributes: [("k".to_string(), "v".to_string())].into(), ..dto("a", "new") };
+        let updated = container.update(input.clone()).await.unwrap();
+
+        assert_eq!(updated.id, input.id);
+        assert_eq!(updated.name, input.name);
+        assert_eq!(updated.attributes, input.attributes);
+    }
+
+    #[tokio::test]
+    async fn update_through_the_handler_round_trips_the_input_fields() {
+        use crate::inject::Handler;
+
+        let handler = Handler::init();
+        let input = DataDto { attributes: [("k".to_string(), "v".to_string())].into(), version: 3, ..dto("a", "new") };
+        let updated = handler.update_data_service().update(input.clone()).await.unwrap();
+
+        assert_eq!(updated.id, input.id);
+        assert_eq!(updated.name, input.name);
+        assert_eq!(updated.attributes, input.attributes);
+        assert_eq!(updated.version, 4);
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn update_through_the_mock_round_trips_the_input_fields() {
+        let container = Container::new(crate::testing::MockRepository::new());
+        container.create(dto("a", "old")).await.unwrap();
+
+        let updated = container.update(dto("a", "new")).await.unwrap();
+
+        assert_eq!(updated.name, "new");
+        assert_eq!(updated.version, 1);
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn deleting_an_existing_row_succeeds() {
+        let container = Container::new(crate::testing::MockRepository::new());
+        container.repo.create(&Data::new("a", "A")).await.unwrap();
+
+        container.delete("a".to_string()).await.unwrap();
+
+        assert!(container.repo.find_by_id("a").await.unwrap().is_none());
+        assert!(container.repo.calls().is_empty());
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn deleting_a_row_that_is_already_gone_succeeds() {
+        let container = Container::new(crate::testing::MockRepository::new());
+        container.repo.create(&Data::new("a", "A")).await.unwrap();
+
+        container.delete("gone".to_string()).await.unwrap();
+
+        assert!(container.repo.find_by_id("a").await.unwrap().is_some());
+        assert_eq!(container.repo.calls().len(), 1);
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn the_mock_records_what_create_stores() {
+        let container = Container::new(crate::testing::MockRepository::new());
+        container.create(dto("a", "A")).await.unwrap();
+
+        let calls = container.repo.calls();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].id().as_ref(), "a");
+        assert_eq!(calls[0].name().as_ref(), "A");
+    }
+
+    #[tokio::test]
+    async fn list_clamps_the_limit_to_the_cap() {
+        let container = Container::new(InMemoryRepository::new());
+        for n in 0..150 {
+            container.repo.create(&Data::new(format!("id-{n}"), "x")).await.unwrap();
+        }
+
+        let page = container.list(0, 10_000).await.unwrap();
+
+        assert_eq!(page.len() as u64, <Container<InMemoryRepository> as ListDataService>::MAX_LIMIT);
+    }
+
+    #[tokio::test]
+    async fn create_propagates_validation_errors() {
+        let container = Container::new(InMemoryRepository::new());
+        assert!(matches!(container.create(dto("", "A")).await, Err(AppError::Validation(_))));
+        assert_eq!(container.repo.count().await.unwrap(), 0);
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn a_rolled_back_transaction_leaves_the_mock_empty() {
+        let repo = crate::testing::MockRepository::new();
+        let mut tx = repo.begin().await.unwrap();
+        repo.create_in(&mut tx, &Data::new("a", "A")).await.unwrap();
+        repo.create_in(&mut tx, &Data::new("b", "B")).await.unwrap();
+
+        tx.rollback().await.unwrap();
+
+        assert!(repo.calls().is_empty());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn a_dto_round_trips_through_json() {
+        let original = DataDto {
+            created_at: Some(OffsetDateTime::UNIX_EPOCH),
+            version: 3,
+            attributes: [("k".to_string(), "v".to_string())].into(),
+            ..dto("a", "A")
+        };
+
+        let json = serde_json::to_string(&original).unwrap();
+
+        assert!(json.contains("\"createdAt\""));
+        assert_eq!(serde_json::from_str::<DataDto>(&json).unwrap(), original);
+    }
+
+    #[test]
+    fn the_handler_threads_its_pool_into_the_repository() {
+        use crate::inject::Handler;
+
+        let handler = Handler::new(Pool::connect("postgres://db/app", 8).unwrap());
+
+        let pool = &handler.repository().0;
+        assert_eq!(pool.url(), "postgres://db/app");
+        assert_eq!(pool.max_connections(), 8);
+    }
+
+    #[tokio::test]
+    async fn an_invalid_form_is_presented_without_reaching_the_repository() {
+        use crate::{adaptor::{_Controller, PresenterA}, UserInputForm, ValidatingTransformer};
+
+        let container = Container::new(InMemoryRepository::new());
+        let called = AtomicBool::new(false);
+
+        let res = _Controller::new(ValidatingTransformer, PresenterA)
+            .transform_checked(UserInputForm { id: "a".to_string(), name: String::new() })
+            .handle(|input| async {
+                called.store(true, Ordering::SeqCst);
+                container.create(input).await
+            }).await;
+
+        assert!(matches!(res, Err(AppError::Validation(_))));
+        assert!(!called.load(Ordering::SeqCst));
+        assert_eq!(container.repo.count().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn get_reports_a_missing_id_as_not_found() {
+        let container = Container::new(InMemoryRepository::new());
+        assert!(matches!(container.get("nope".to_string()).await, Err(AppError::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn get_returns_the_stored_record() {
+        let container = Container::new(InMemoryRepository::new());
+        container.repo.create(&Data::new("a", "A")).await.unwrap();
+
+        let found = container.get("a".to_string()).await.unwrap();
+
+        assert_eq!(found.id, "a");
+        assert_eq!(found.name, "A");
+    }
+
+    #[test]
+    fn driver_errors_collapse_into_internal() {
+        let e: AppError = KernelError::Driver("socket closed".to_string()).into();
+        assert!(matches!(e, AppError::Internal));
+    }
+
+    #[test]
+    fn not_found_stays_distinct() {
+        let e: AppError = KernelError::NotFound.into();
+        assert!(matches!(e, AppError::NotFound));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn http_statuses_follow_the_kernel_error() {
+        use crate::adaptor::{HttpPresenter, OutPort};
+
+        let presenter = HttpPresenter::new();
+        let cases = [
+            (KernelError::NotFound, 404),
+            (KernelError::Conflict, 409),
+            (KernelError::Validation("bad".to_string()), 400),
+            (KernelError::TooLarge { len: 2, max: 1 }, 413),
+            (KernelError::Cancelled, 499),
+            (KernelError::Driver("down".to_string()), 500),
+            (KernelError::Timeout, 500),
+            (KernelError::Unavailable, 503),
+        ];
+        for (error, status) in cases {
+            assert_eq!(presenter.emit(Err(error.clone().into())).0, status, "{error:?}");
+        }
+        assert_eq!(presenter.emit(Ok(dto("a", "A"))).0, 200);
+    }
+
+    #[tokio::test]
+    async fn a_slow_call_times_out_and_is_dropped() {
+        let probe = ProbeRepository::parked();
+        let abandoned = Arc::clone(&probe.abandoned_reads);
+        let repo = TimeoutRepository(probe, std::time::Duration::from_millis(10));
+
+        assert!(matches!(repo.find_by_id("a").await, Err(KernelError::Timeout)));
+        assert_eq!(abandoned.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn create_is_retried_until_it_succeeds() {
+        let policy = RetryPolicy { max_retries: 3, base_delay: std::time::Duration::from_millis(1) };
+        let repo = RetryRepository(ProbeRepository::failing_creates(2), policy);
+
+        repo.create(&Data::new("a", "A")).await.unwrap();
+
+        assert_eq!(repo.0.create_attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn in_memory_reads_back_what_it_stored() {
+        let repo = InMemoryRepository::new();
+        let data = Data::new("a", "A");
+        repo.create(&data).await.unwrap();
+        assert_eq!(repo.find_by_id("a").await.unwrap(), Some(data));
+    }
+
+    #[tokio::test]
+    async fn in_memory_rejects_a_duplicate_create() {
+        let repo = InMemoryRepository::new();
+        repo.create(&Data::new("a", "A")).await.unwrap();
+        assert!(matches!(repo.create(&Data::new("a", "B")).await, Err(KernelError::Conflict)));
+    }
+
+    #[tokio::test]
+    async fn a_fixed_clock_pins_created_at() {
+        let clock = FixedClock(OffsetDateTime::UNIX_EPOCH + Duration::hours(1));
+        let container = Container::new(InMemoryRepository::new()).with_clock(clock);
+
+        let created = container.create(dto("a", "A")).await.unwrap();
+
+        assert_eq!(created.created_at, Some(clock.0));
+    }
+
+    #[tokio::test]
+    async fn capture2_hands_both_inputs_to_the_closure_unmodified() {
+        use crate::adaptor::{Controller, PresenterA};
+
+        let id = DataId::new("a").unwrap();
+        let patch = dto("b", "patched");
+        let received = Mutex::new(None);
+
+        let _ = Controller::new(PresenterA)
+            .capture2(id.clone(), patch.clone())
+            .handle(|id: DataId, patch: DataDto| {
+                *received.lock().unwrap() = Some((id, patch.clone()));
+                async { Ok(patch) }
+            }).await;
+
+        assert_eq!(received.into_inner().unwrap(), Some((id, patch)));
+    }
+
+    #[tokio::test]
+    async fn a_mapped_presenter_wraps_the_view_model() {
+        use crate::adaptor::{Controller, OutPort, PresenterB};
+
+        let presenter = PresenterB.map(|vm: Result<String, AppError>| vm.map(|body| format!("{{\"payload\": {body:?}}}")));
+
+        let res = Controller::new(presenter)
+            .capture(dto("a", "A"))
+            .handle(|input: DataDto| async { Ok(input) })
+            .await;
+
+        assert_eq!(res.unwrap(), format!("{{\"payload\": {:?}}}", format!("{:?}", dto("a", "A"))));
+    }
+
+    #[test]
+    fn the_builder_requires_a_name() {
+        assert!(matches!(DataDto::builder().id("a").build(), Err(AppError::Validation(_))));
+    }
+
+    /// Collects what a `tracing` subscriber writes, for asserting on log lines.
+    #[cfg(feature = "tracing")]
+    #[derive(Clone, Default)]
+    struct LogCapture(Arc<Mutex<Vec<u8>>>);
+
+    #[cfg(feature = "tracing")]
+    impl LogCapture {
+        fn install(&self) -> tracing::subscriber::DefaultGuard {
+            let capture = self.clone();
+            let subscriber = tracing_subscriber::fmt()
+                .with_writer(move || capture.clone())
+                .with_ansi(false)
+                .without_time()
+                .finish();
+            tracing::subscriber::set_default(subscriber)
+        }
+
+        fn output(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    impl std::io::Write for LogCapture {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    async fn the_timing_layer_reports_the_elapsed_time() {
+        use crate::adaptor::{Controller, PresenterA, TimingLayer};
+
+        let capture = LogCapture::default();
+        let _guard = capture.install();
+
+        let _ = Controller::with_layers(PresenterA, vec![Box::new(TimingLayer)])
+            .capture(dto("a", "A"))
+            .handle(|input: DataDto| async { Ok(input) })
+            .await;
+
+        let output = capture.output();
+        assert!(output.contains("handled"), "{output}");
+        assert!(output.contains("elapsed="), "{output}");
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    async fn with_logging_logs_a_successful_transform() {
+        use crate::adaptor::{_Controller, PresenterA};
+
+        let capture = LogCapture::default();
+        let _guard = capture.install();
+
+        let _ = _Controller::new(Identity, PresenterA)
+            .with_logging()
+            .transform(dto("a", "A"))
+            .handle(|input| async { Ok(input) })
+            .await;
+
+        let output = capture.output();
+        assert!(output.contains("controller"), "{output}");
+        assert!(output.contains("outcome=\"ok\""), "{output}");
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    async fn with_logging_logs_a_failed_capture() {
+        use crate::adaptor::{Controller, PresenterA};
+
+        let capture = LogCapture::default();
+        let _guard = capture.install();
+
+        let _ = Controller::new(PresenterA)
+            .with_logging()
+            .capture(dto("a", "A"))
+            .handle(|_: DataDto| async { Err::<DataDto, _>(AppError::NotFound) })
+            .await;
+
+        let output = capture.output();
+        assert!(output.contains("outcome=\"error\""), "{output}");
+        assert!(output.contains(&format!("code={}", AppError::NotFound.code())), "{output}");
+    }
+
+    #[tokio::test]
+    async fn count_reports_every_stored_record() {
+        let container = Container::new(InMemoryRepository::new());
+        for id in ["a", "b", "c"] {
+            container.repo.create(&Data::new(id, "x")).await.unwrap();
+        }
+        assert_eq!(container.count().await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn a_soft_deleted_record_is_hidden_from_find_by_id() {
+        let repo = InMemoryRepository::with_soft_delete();
+        repo.create(&Data::new("a", "A")).await.unwrap();
+        repo.delete("a").await.unwrap();
+        assert_eq!(repo.find_by_id("a").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn a_soft_deleted_record_is_visible_including_deleted() {
+        let repo = InMemoryRepository::with_soft_delete();
+        repo.create(&Data::new("a", "A")).await.unwrap();
+        repo.delete("a").await.unwrap();
+
+        let data = repo.find_by_id_including_deleted("a").await.unwrap().unwrap();
+
+        assert!(data.is_deleted());
+    }
+
+    /// Written against nothing but the prelude.
+    mod prelude_only {
+        use crate::prelude::*;
+
+        pub async fn create<H, P>(handler: &H, presenter: P, input: DataDto) -> P::ViewModel
+            where H: DependOnCreateDataService,
+                  P: OutPort<Result<DataDto, AppError>>
+        {
+            Controller::new(presenter)
+                .capture(input)
+                .handle(|input| async {
+                    handler.create_simple_data_service()
+                        .create(input)
+                        .await
+                }).await
+        }
+    }
+
+    #[tokio::test]
+    async fn a_handler_can_be_written_with_only_the_prelude() {
+        use crate::{adaptor::PresenterA, inject::Handler};
+
+        let res = prelude_only::create(&Handler::init(), PresenterA, dto("a", "A")).await;
+
+        assert_eq!(res.unwrap().id, "a");
+    }
+
+    #[tokio::test]
+    async fn the_presented_id_can_be_read_off_the_view_model() {
+        use crate::adaptor::{_Controller, PresenterA};
+
+        let container = Container::new(InMemoryRepository::new());
+
+        let view = _Controller::new(Identity, PresenterA)
+            .transform(dto("a", "A"))
+            .handle(|input| async { container.create(input).await })
+            .await
+            .unwrap();
+
+        assert_eq!(view.id, "a");
+    }
+
+    #[tokio::test]
+    async fn the_stream_yields_every_stored_record() {
+        use futures::StreamExt;
+
+        let container = Container::new(InMemoryRepository::new());
+        for id in ["a", "b", "c"] {
+            container.repo.create(&Data::new(id, "x")).await.unwrap();
+        }
+
+        let ids = container.stream()
+            .map(|res| res.unwrap().id)
+            .collect::<Vec<_>>().await;
+
+        assert_eq!(ids, ["a", "b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn dropping_the_stream_early_releases_the_store() {
+        use futures::StreamExt;
+
+        let container = Container::new(InMemoryRepository::new());
+        for id in ["a", "b", "c"] {
+            container.repo.create(&Data::new(id, "x")).await.unwrap();
+        }
+
+        let mut stream = container.stream();
+        stream.next().await.unwrap().unwrap();
+        drop(stream);
+
+        let data = Data::new("d", "x");
+        tokio::time::timeout(std::time::Duration::from_secs(1), container.repo.create(&data)).await
+            .expect("the store is still locked")
+            .unwrap();
+    }
+
+    #[cfg(feature = "serde")]
+    fn problem(e: AppError) -> serde_json::Value {
+        use crate::adaptor::{OutPort, ProblemJsonPresenter};
+
+        let body = ProblemJsonPresenter.emit(Err(e)).unwrap_err();
+        serde_json::from_str(&body).unwrap()
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn not_found_renders_as_a_problem() {
+        assert_eq!(problem(AppError::NotFound), serde_json::json!({
+            "type": "about:blank",
+            "title": "Not Found",
+            "status": 404,
+            "detail": AppError::NotFound.to_string(),
+        }));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn a_validation_error_renders_as_a_problem() {
+        let e = AppError::Validation("name must not be blank".to_string());
+        assert_eq!(problem(e.clone()), serde_json::json!({
+            "type": "about:blank",
+            "title": "Bad Request",
+            "status": 400,
+            "detail": e.to_string(),
+        }));
+    }
+
+    #[tokio::test]
+    async fn upserting_a_new_record_inserts_it() {
+        let repo = InMemoryRepository::new();
+        assert!(repo.upsert(&Data::new("a", "A")).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn upserting_an_existing_record_overwrites_it() {
+        let repo = InMemoryRepository::new();
+        repo.create(&Data::new("a", "A")).await.unwrap();
+
+        assert!(!repo.upsert(&Data::new("a", "B")).await.unwrap());
+        assert_eq!(repo.find_by_id("a").await.unwrap().unwrap().name().as_ref(), "B");
+    }
+
+    #[tokio::test]
+    async fn upserting_an_existing_record_keeps_its_created_at() {
+        let repo = InMemoryRepository::new();
+        let epoch = FixedClock(OffsetDateTime::UNIX_EPOCH);
+        repo.create(&Data::try_new(DataId::new("a").unwrap(), "A", &epoch).unwrap()).await.unwrap();
+
+        repo.upsert(&Data::new("a", "B")).await.unwrap();
+
+        let stored = repo.find_by_id("a").await.unwrap().unwrap();
+        assert_eq!(stored.created_at(), OffsetDateTime::UNIX_EPOCH);
+        assert_eq!(stored.version(), 1);
+    }
+
+    #[tokio::test]
+    async fn upserting_a_soft_deleted_record_conflicts_like_create() {
+        let repo = InMemoryRepository::with_soft_delete();
+        repo.create(&Data::new("a", "A")).await.unwrap();
+        repo.delete("a").await.unwrap();
+
+        assert!(matches!(repo.upsert(&Data::new("a", "B")).await, Err(KernelError::Conflict)));
+        assert!(repo.find_by_id("a").await.unwrap().is_none());
+    }
+
+    #[cfg(feature = "testing")]
+    mod split {
+        use super::*;
+        use crate::testing::MockRepository;
+
+        /// Reads from one mock and writes to another.
+        /// Everything but reads is served by `writes`.
+        struct Split {
+            reads: MockRepository,
+            writes: Container<MockRepository>
+        }
+
+        impl DependOnReadRepository for Split {
+            type ReadRepository = MockRepository;
+            fn read_repository(&self) -> &MockRepository {
+                &self.reads
+            }
+        }
+
+        impl DependOnWriteRepository for Split {
+            type WriteRepository = MockRepository;
+            fn write_repository(&self) -> &MockRepository {
+                &self.writes.repo
+            }
+        }
+
+        impl DependOnEventPublisher for Split {
+            type EventPublisher = NoopEventPublisher;
+            fn event_publisher(&self) -> &NoopEventPublisher {
+                self.writes.event_publisher()
+            }
+        }
+
+        impl DependOnIdGenerator for Split {
+            type IdGenerator = SequenceGenerator;
+            fn id_generator(&self) -> &SequenceGenerator {
+                self.writes.id_generator()
+            }
+        }
+
+        impl DependOnAuditLog for Split {
+            type AuditLog = NoopAuditLog;
+            fn audit_log(&self) -> &NoopAuditLog {
+                self.writes.audit_log()
+            }
+        }
+
+        impl DependOnClock for Split {
+            type Clock = SystemClock;
+            fn clock(&self) -> &SystemClock {
+                self.writes.clock()
+            }
+        }
+
+        impl CreateDataService for Split {}
+
+        #[tokio::test]
+        async fn creates_and_reads_use_separate_repositories() {
+            let split = Split { reads: MockRepository::new(), writes: Container::new(MockRepository::new()) };
+
+            split.create(dto("a", "A")).await.unwrap();
+
+            assert_eq!(split.writes.repo.calls().len(), 1);
+            assert!(split.reads.calls().is_empty());
+            assert!(matches!(split.get("a".to_string()).await, Err(AppError::NotFound)));
+        }
+    }
+
+    #[tokio::test]
+    async fn capture_ref_leaves_the_input_with_the_caller() {
+        use crate::adaptor::{Controller, PresenterA};
+
+        let id = "a".to_string();
+
+        let view = Controller::new(PresenterA)
+            .capture_ref(&id)
+            .handle(|id: String| async move { Ok(dto(&id, "A")) })
+            .await
+            .unwrap();
+
+        assert_eq!(view.id, id);
+    }
+
+    #[tokio::test]
+    async fn a_freshly_connected_pool_passes_the_health_check() {
+        let handler = crate::inject::Handler::new(Pool::connect("postgres://db/app", 4).unwrap());
+        handler.health_check().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn an_unreachable_pool_fails_ping() {
+        let pool = Pool::connect("localhost", 4).unwrap();
+        assert!(matches!(pool.ping().await, Err(KernelError::Driver(_))));
+    }
+
+    /// Keeps every published event, in order.
+    #[derive(Default)]
+    struct RecordingPublisher(Mutex<Vec<DomainEvent>>);
+
+    impl RecordingPublisher {
+        fn events(&self) -> Vec<DomainEvent> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl EventPublisher for RecordingPublisher {
+        async fn publish(&self, event: DomainEvent) {
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_successful_create_publishes_one_event() {
+        let container = Container::new(InMemoryRepository::new()).with_publisher(RecordingPublisher::default());
+
+        container.create(dto("a", "A")).await.unwrap();
+
+        let events = container.publisher.events();
+        assert_eq!(events.len(), 1);
+        assert!(matches!(&events[0], DomainEvent::DataCreated { id } if id.as_ref() == "a"));
+    }
+
+    #[tokio::test]
+    async fn a_failed_create_publishes_nothing() {
+        let container = Container::new(ProbeRepository::failing_creates(1)).with_publisher(RecordingPublisher::default());
+
+        assert!(container.create(dto("a", "A")).await.is_err());
+        assert!(container.publisher.events().is_empty());
+    }
+
+    #[tokio::test]
+    async fn an_update_at_the_stored_version_bumps_it() {
+        let repo = InMemoryRepository::new();
+        repo.create(&Data::new("a", "A")).await.unwrap();
+
+        assert!(repo.update(&Data::new("a", "B"), 0).await.unwrap());
+        assert_eq!(repo.find_by_id("a").await.unwrap().unwrap().version(), 1);
+    }
+
+    #[tokio::test]
+    async fn an_update_at_a_stale_version_is_a_conflict() {
+        let container = Container::new(InMemoryRepository::new());
+        container.repo.create(&Data::new("a", "A")).await.unwrap();
+        container.update(dto("a", "B")).await.unwrap();
+
+        assert!(matches!(container.update(dto("a", "C")).await, Err(AppError::Conflict)));
+    }
+
+    #[tokio::test]
+    async fn metrics_time_a_successful_invocation() {
+        use crate::adaptor::{Controller, PresenterA};
+
+        let (view, metrics) = Controller::new(PresenterA)
+            .capture(dto("a", "A"))
+            .handle_with_metrics(|input: DataDto| async {
+                tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+                Ok(input)
+            }).await;
+
+        assert!(view.is_ok());
+        assert!(metrics.success);
+        assert!(metrics.elapsed > std::time::Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn metrics_report_a_failed_invocation() {
+        use crate::adaptor::{Controller, PresenterA};
+
+        let (view, metrics) = Controller::new(PresenterA)
+            .capture(dto("a", "A"))
+            .handle_with_metrics(|_: DataDto| async { Err(AppError::Conflict) })
+            .await;
+
+        assert!(view.is_err());
+        assert!(!metrics.success);
+    }
+
+    #[cfg(feature = "axum")]
+    #[tokio::test]
+    async fn the_create_handler_responds_with_the_created_record() {
+        use ::axum::{body, extract::State, http::StatusCode, response::IntoResponse, Json};
+
+        use crate::{inject::Handler, integration::axum::create_data};
+
+        let response = create_data(State(Handler::init().shared()), Json(dto("a", "A"))).await.into_response();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["id"], "a");
+        assert_eq!(body["name"], "A");
+    }
+
+    #[test]
+    fn trimming_then_transformer_a_yields_a_trimmed_name() {
+        use crate::{adaptor::InPort, TransformerA, TrimmingTransformer, UserInputForm};
+
+        let dto = TrimmingTransformer.then(TransformerA)
+            .emit(UserInputForm { id: "a".to_string(), name: "  test man  ".to_string() });
+
+        assert_eq!(dto.name, "test man");
+    }
+
+    #[test]
+    fn records_built_from_the_same_fields_are_equal() {
+        assert_eq!(Data::new("a", "b"), Data::new("a", "b"));
+    }
+
+    #[tokio::test]
+    async fn a_patch_changes_only_the_name() {
+        let container = Container::new(InMemoryRepository::new());
+        let original = Data::new("a", "old").with_extra([("k".to_string(), "v".to_string())].into());
+        container.repo.create(&original).await.unwrap();
+
+        let patched = container.patch("a".to_string(), DataPatch { name: Some("new".to_string()) }).await.unwrap();
+
+        assert_eq!(patched.name, "new");
+        assert_eq!(patched.attributes, *original.extra());
+        assert_eq!(container.repo.find_by_id("a").await.unwrap().unwrap().name().as_ref(), "new");
+    }
+
+    #[tokio::test]
+    async fn an_empty_patch_is_a_no_op() {
+        let container = Container::new(InMemoryRepository::new());
+        container.repo.create(&Data::new("a", "A")).await.unwrap();
+
+        let unchanged = container.patch("a".to_string(), DataPatch::default()).await.unwrap();
+
+        assert_eq!(unchanged.name, "A");
+        assert_eq!(unchanged.version, 0);
+        assert_eq!(container.repo.find_by_id("a").await.unwrap().unwrap().version(), 0);
+    }
+
+    #[tokio::test]
+    async fn allow_overwrite_replaces_a_duplicate_create() {
+        let repo = InMemoryRepository::new().allow_overwrite(true);
+        repo.create(&Data::new("a", "A")).await.unwrap();
+
+        repo.create(&Data::new("a", "B")).await.unwrap();
+
+        assert_eq!(repo.find_by_id("a").await.unwrap().unwrap().name().as_ref(), "B");
+    }
+
+    // `tests/ui` can only link the library, and `ControllerBuilder` lives in the binary's adaptor
+    // layer next to the `OutPort`s it builds with, so the missing-presenter case is checked at
+    // compile time here instead of through `trybuild`. Inherent methods win over trait methods, so
+    // `build` only resolves to `Fallback::build` when the builder has none of its own.
+    #[test]
+    fn a_builder_without_a_presenter_has_no_build() {
+        use crate::adaptor::{_Controller, PresenterA};
+
+        struct NoBuild;
+
+        trait Fallback: Sized {
+            fn build<I, D, O>(self) -> NoBuild {
+                NoBuild
+            }
+        }
+
+        impl<T> Fallback for T {}
+
+        type Builder = _Controller<(), (), (), (), ()>;
+
+        let _: NoBuild = Builder::builder().transformer(Identity).build::<(), (), ()>();
+        let _: _Controller<Identity, PresenterA, DataDto, DataDto, Result<DataDto, AppError>> =
+            Builder::builder().transformer(Identity).presenter(PresenterA).build();
+    }
+
+    #[tokio::test]
+    async fn a_cancelled_token_stops_before_the_repository() {
+        use tokio_util::sync::CancellationToken;
+
+        use crate::adaptor::{Controller, PresenterA};
+
+        let container = Container::new(InMemoryRepository::new());
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        let res = Controller::new(PresenterA)
+            .capture(dto("a", "A"))
+            .handle_cancellable(Some(&cancel), |input: DataDto| container.create_cancellable(input, Some(&cancel)))
+            .await;
+
+        assert!(matches!(res, Err(AppError::Cancelled)));
+        assert_eq!(container.repo.count().await.unwrap(), 0);
+    }
+
+    #[test]
+    fn validate_reports_every_problem_at_once() {
+        let problems = dto("", " ").validate().unwrap_err();
+        assert_eq!(problems.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn a_cache_hit_does_not_reach_the_inner_repository() {
+        let probe = ProbeRepository::default();
+        probe.inner.create(&Data::new("a", "A")).await.unwrap();
+        let cache = CachingRepository::new(probe, 4);
+
+        cache.find_by_id("a").await.unwrap();
+        cache.find_by_id("a").await.unwrap();
+
+        assert_eq!(cache.inner().find_calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn an_update_invalidates_the_cached_entry() {
+        let probe = ProbeRepository::default();
+        probe.inner.create(&Data::new("a", "old")).await.unwrap();
+        let cache = CachingRepository::new(probe, 4);
+        cache.find_by_id("a").await.unwrap();
+
+        cache.update(&Data::new("a", "new"), 0).await.unwrap();
+
+        assert_eq!(cache.find_by_id("a").await.unwrap().unwrap().name().as_ref(), "new");
+        assert_eq!(cache.inner().find_calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn converting_a_borrowed_record_leaves_it_usable() {
+        let data = Data::new("a", "A");
+        let dto = DataDto::from(&data);
+        assert_eq!(dto.id, data.id().as_ref());
+        assert_eq!(dto.name, data.name().as_ref());
+    }
+
+    #[test]
+    fn not_found_maps_to_err_not_found() {
+        use crate::{adaptor::{status_code, OutPort, PresenterA}, kernel::codes::ERR_NOT_FOUND};
+
+        assert_eq!(status_code(&AppError::NotFound), ERR_NOT_FOUND);
+        assert!(matches!(PresenterA.emit(Err(KernelError::NotFound.into())), Err(AppError::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn two_tasks_create_through_one_shared_handler() {
+        use crate::inject::Handler;
+
+        let handler = Handler::init().shared();
+        let tasks = ["a", "b"].map(|id| {
+            let handler = Arc::clone(&handler);
+            tokio::spawn(async move {
+                handler.create_simple_data_service().create(dto(id, "x")).await
+            })
+        });
+
+        for (task, id) in tasks.into_iter().zip(["a", "b"]) {
+            assert_eq!(task.await.unwrap().unwrap().id, id);
+        }
+    }
+
+    #[tokio::test]
+    async fn inspect_sees_the_intermediate_dto() {
+        use crate::{adaptor::{Pipeline, PresenterA}, TransformerA, UserInputForm};
+
+        let mut seen = None;
+
+        let view = Pipeline::new(TransformerA, PresenterA)
+            .input(UserInputForm { id: "a".to_string(), name: "A".to_string() })
+            .transform()
+            .inspect(|dto| seen = Some(dto.clone()))
+            .execute(|dto| async { Ok(dto) }).await
+            .present();
+
+        assert_eq!(seen, Some(dto("a", "A")));
+        assert_eq!(view.unwrap().id, "a");
+    }
+
+    #[tokio::test]
+    async fn an_empty_id_fails_conversion_before_the_repository() {
+        assert!(matches!(Data::try_from(dto("", "A")), Err(KernelError::Validation(_))));
+
+        let container = Container::new(ProbeRepository::default());
+        assert!(matches!(container.create(dto("", "A")).await, Err(AppError::Validation(_))));
+        assert_eq!(container.repo.create_attempts.load(Ordering::SeqCst), 0);
+    }
+
+    async fn searchable() -> Container<InMemoryRepository> {
+        let container = Container::new(InMemoryRepository::new());
+        for (id, name) in [("a", "apple"), ("b", "apricot"), ("c", "banana")] {
+            container.repo.create(&Data::new(id, name)).await.unwrap();
+        }
+        container
+    }
+
+    #[tokio::test]
+    async fn search_returns_names_with_the_prefix() {
+        let found = searchable().await.search("ap", 10).await.unwrap();
+        let mut names = found.into_iter().map(|dto| dto.name).collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["apple", "apricot"]);
+    }
+
+    #[tokio::test]
+    async fn search_with_an_unmatched_prefix_is_empty() {
+        assert!(searchable().await.search("zz", 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn search_with_an_empty_prefix_returns_up_to_limit() {
+        assert_eq!(searchable().await.search("", 2).await.unwrap().len(), 2);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn a_serialization_failure_becomes_an_internal_error() {
+        use crate::adaptor::{HttpPresenter, JsonPresenter, OutPort};
+
+        // RFC 3339 has no representation for years before 0, so this DTO cannot be serialized.
+        let unserializable = DataDto { created_at: Some(OffsetDateTime::UNIX_EPOCH.replace_year(-1).unwrap()), ..dto("a", "A") };
+
+        assert!(matches!(JsonPresenter::new(false).emit(Ok(unserializable.clone())), Err(AppError::Internal)));
+        assert_eq!(HttpPresenter::new().emit(Ok(unserializable)).0, 500);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn close_rejects_new_work_while_the_running_create_finishes() {
+        use std::sync::mpsc;
+
+        let (started_tx, started) = mpsc::channel();
+        let (release, release_rx) = mpsc::channel::<()>();
+        let (started_tx, release_rx) = (Mutex::new(started_tx), Mutex::new(release_rx));
+        // The create logs while holding its in-flight slot, so blocking the sink there keeps it running.
+        let repo = DataRepository::new(Pool::in_memory()).with_sink(move |message: &str| {
+            if message.contains("ctx=") {
+                started_tx.lock().unwrap().send(()).unwrap();
+                release_rx.lock().unwrap().recv().unwrap();
+            }
+        });
+
+        let running = tokio::spawn({
+            let repo = repo.clone();
+            async move { repo.create(&Data::new("a", "A")).await }
+        });
+        started.recv().unwrap();
+        let closed = tokio::spawn(repo.0.close());
+
+        assert!(matches!(repo.create(&Data::new("b", "B")).await, Err(KernelError::Unavailable)));
+        release.send(()).unwrap();
+        running.await.unwrap().unwrap();
+        closed.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn handle_try_presents_the_closure_error() {
+        use crate::adaptor::{Controller, PresenterA};
+
+        let view = Controller::new(PresenterA)
+            .capture(dto("a", "A"))
+            .handle_try(|_: DataDto| async { Err::<DataDto, _>(KernelError::Conflict) })
+            .await;
+
+        assert!(matches!(view, Err(AppError::Conflict)));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn create_counts_successes_and_failures() {
+        use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let container = Container::new(InMemoryRepository::new());
+
+        metrics::with_local_recorder(&recorder, || futures::executor::block_on(async {
+            container.create(dto("a", "A")).await.unwrap();
+            container.create(dto("a", "A")).await.unwrap_err();
+        }));
+
+        let mut counters = snapshotter.snapshot().into_vec().into_iter()
+            .map(|(key, _, _, value)| {
+                let key = key.key();
+                let labels = key.labels().map(|label| format!("{}={}", label.key(), label.value())).collect::<Vec<_>>();
+                (key.name().to_string(), labels, value)
+            })
+            .collect::<Vec<_>>();
+        counters.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(counters, vec![
+            ("data_create_errors_total".to_string(), vec!["code=409".to_string()], DebugValue::Counter(1)),
+            ("data_created_total".to_string(), vec![], DebugValue::Counter(1)),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn records_created_under_a_frozen_clock_share_the_timestamp() {
+        let container = Container::new(InMemoryRepository::new()).with_clock(FixedClock(OffsetDateTime::UNIX_EPOCH));
+
+        let a = container.create(dto("a", "A")).await.unwrap();
+        let b = container.create(dto("b", "B")).await.unwrap();
+
+        assert_eq!(a.created_at, b.created_at);
+    }
+
+    #[tokio::test]
+    async fn both_controller_shapes_run_through_controller_like() {
+        use crate::adaptor::{_Controller, Controller, ControllerLike, PresenterA};
+
+        async fn echo<C>(controller: C) -> C::ViewModel
+            where C: ControllerLike<DataDto, Input = DataDto, Output = Result<DataDto, AppError>>
+        {
+            controller.run(dto("a", "A"), |input| async { Ok(input) }).await
+        }
+
+        assert_eq!(echo(_Controller::new(Identity, PresenterA)).await.unwrap().id, "a");
+        assert_eq!(echo(Controller::new(PresenterA)).await.unwrap().id, "a");
+    }
+
+    #[tokio::test]
+    async fn the_tenant_set_at_the_controller_reaches_the_repository() {
+        use crate::adaptor::{ContextLayer, Controller, PresenterA};
+
+        let container = Container::new(ProbeRepository::default());
+        let context = RequestContext { request_id: "req-1".to_string(), tenant_id: Some("tenant-a".to_string()) };
+
+        Controller::with_layers(PresenterA, vec![Box::new(ContextLayer(context.clone()))])
+            .capture(dto("a", "A"))
+            .handle(|input| container.create(input))
+            .await
+            .unwrap();
+
+        assert_eq!(*container.repo.create_contexts.lock().unwrap(), vec![Some(context)]);
+    }
 
-    #[derive(Clone)]
-    pub struct Pool;
-    
-    #[derive(Clone)]
-    pub struct DataRepository(pub Pool);
+    #[cfg(feature = "serde")]
+    #[tokio::test]
+    async fn handle_stream_renders_one_json_line_per_record() {
+        use futures::StreamExt;
 
-    #[async_trait::async_trait]
-    impl Repository for DataRepository {
-        async fn create(&self, data: &Data) -> Result<(), u64> {
-            println!("[driver] : {:?}", data);
-            Ok(())
+        use crate::adaptor::{_Controller, JsonLinesPresenter};
+
+        let container = Container::new(InMemoryRepository::new());
+        for id in ["a", "b"] {
+            container.repo.create(&Data::new(id, "x")).await.unwrap();
         }
+        let expected = container.stream()
+            .map(|dto| format!("{}\n", serde_json::to_string(&dto.unwrap()).unwrap()))
+            .collect::<Vec<_>>().await;
+
+        let lines = _Controller::new(Identity, JsonLinesPresenter)
+            .handle_stream(dto("ignored", "x"), |_| container.stream())
+            .collect::<Vec<_>>().await;
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines, expected);
     }
-}
 
-/// A.k.a Domain Layer
-pub mod kernel {
-    #[derive(Debug, Clone, destructure::Destructure)]
-    pub struct Data {
-        id: String,
-        name: String,
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn instrumented_create_records_a_latency_sample() {
+        use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let repo = InstrumentedRepository::new(InMemoryRepository::new());
+
+        metrics::with_local_recorder(&recorder, || futures::executor::block_on(repo.create(&Data::new("a", "A")))).unwrap();
+
+        let samples = snapshotter.snapshot().into_vec().into_iter()
+            .find_map(|(key, _, _, value)| {
+                let key = key.key();
+                let op = key.labels().find(|label| label.key() == "op").map(|label| label.value().to_string());
+                (key.name() == "repository_operation_duration_seconds" && op.as_deref() == Some("create")).then_some(value)
+            });
+        assert!(matches!(samples, Some(DebugValue::Histogram(samples)) if samples.len() == 1));
     }
 
-    impl Data {
-        pub fn new(id: impl Into<String>, name: impl Into<String>) -> Self {
-            Self { id: id.into(), name: name.into() }
-        }
+    #[tokio::test]
+    async fn extra_attributes_survive_create_and_get() {
+        let container = Container::new(InMemoryRepository::new());
+        let attributes: BTreeMap<_, _> = [("color", "red"), ("size", "L")].map(|(k, v)| (k.to_string(), v.to_string())).into();
+
+        container.create(DataDto { attributes: attributes.clone(), ..dto("a", "A") }).await.unwrap();
+
+        assert_eq!(container.get("a".to_string()).await.unwrap().attributes, attributes);
     }
 
-    #[async_trait::async_trait]
-    pub trait Repository: 'static + Send + Sync {
-        async fn create(&self, data: &Data) -> Result<(), u64>;
+    fn localized(locale: &str) -> crate::adaptor::LocalizedPresenter {
+        let catalog = [
+            ("en", vec![("not_found", "Not found"), ("conflict", "Already exists")]),
+            ("ja", vec![("not_found", "見つかりません")]),
+        ].into_iter()
+            .map(|(locale, messages)| (
+                locale.to_string(),
+                messages.into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            ))
+            .collect();
+        crate::adaptor::LocalizedPresenter::new(locale, catalog)
     }
 
-    pub trait DependOnRepository: 'static + Send + Sync {
-        type Repository: Repository;
-        fn repository(&self) -> &Self::Repository;
+    #[test]
+    fn a_present_locale_is_used() {
+        use crate::adaptor::OutPort;
+        assert_eq!(localized("ja").emit(Err(AppError::NotFound)).unwrap_err(), "見つかりません");
     }
-}
 
-/// A.k.a UseCase Layer
-pub mod application {
-    use crate::kernel::{DependOnRepository, Repository, Data, DestructData};
+    #[test]
+    fn a_missing_key_falls_back_to_english() {
+        use crate::adaptor::OutPort;
+        assert_eq!(localized("ja").emit(Err(AppError::Conflict)).unwrap_err(), "Already exists");
+    }
 
-    #[derive(Debug, Clone)]
-    pub struct DataDto {
-        pub id: String,
-        pub name: String
+    #[test]
+    fn an_unknown_locale_falls_back_to_english() {
+        use crate::adaptor::OutPort;
+        assert_eq!(localized("fr").emit(Err(AppError::NotFound)).unwrap_err(), "Not found");
     }
 
-    impl From<Data> for DataDto {
-        fn from(value: Data) -> Self {
-            let DestructData {
-                id,
-                name
-            } = value.into_destruct();
-            Self { id, name }
-        }
+    #[tokio::test]
+    async fn a_failed_transaction_closure_leaves_the_store_unchanged() {
+        let repo = InMemoryRepository::new();
+
+        let res = repo.transaction(|repo, tx| Box::pin(async move {
+            repo.create_in(tx, &Data::new("a", "A")).await?;
+            Err::<(), _>(KernelError::Conflict)
+        })).await;
+
+        assert!(matches!(res, Err(KernelError::Conflict)));
+        assert_eq!(repo.count().await.unwrap(), 0);
     }
 
-    #[async_trait::async_trait]
-    pub trait CreateDataService: 'static + Send + Sync
-        + DependOnRepository
-    {
-        async fn create(&self, obj: DataDto) -> Result<DataDto, u64> {
-            let DataDto { id, name } = obj;
-            let data = Data::new(id, name); 
-            self.repository().create(&data).await?;
-            Ok(data.into())
-        }
+    #[tokio::test]
+    async fn the_create_log_line_carries_the_record_id() {
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let repo = DataRepository::new(Pool::in_memory()).with_sink({
+            let messages = Arc::clone(&messages);
+            move |message: &str| messages.lock().unwrap().push(message.to_string())
+        });
+
+        repo.create(&Data::new("record-42", "A")).await.unwrap();
+
+        assert!(messages.lock().unwrap().iter().any(|message| message.contains("record-42")));
     }
 
-    // Default Impl
-    impl<T> CreateDataService for T
-        where T: DependOnRepository {}
+    #[cfg(feature = "serde")]
+    #[test]
+    fn a_handler_is_built_from_a_json_config() {
+        use crate::inject::{Handler, HandlerConfig, LogLevel};
 
-    pub trait DependOnCreateDataService: 'static + Send + Sync {
-        type CreateDataService: CreateDataService;
-        fn create_simple_data_service(&self) -> &Self::CreateDataService;
+        let cfg: HandlerConfig = serde_json::from_str(r#"{ "database_url": "postgres://db/app", "pool_size": 4, "log_level": "debug" }"#).unwrap();
+        assert_eq!(cfg.log_level, LogLevel::Debug);
+
+        let handler = Handler::from_config(cfg).unwrap();
+
+        assert_eq!(handler.repository().0.url(), "postgres://db/app");
+        assert_eq!(handler.repository().0.max_connections(), 4);
     }
-}
 
-/// A.k.a DI Container
-pub mod inject {
-    use crate::{
-        kernel::{DependOnRepository, Repository},
-        driver::{DataRepository, Pool}, 
-        application::DependOnCreateDataService, 
-    };
+    #[cfg(feature = "serde")]
+    #[tokio::test]
+    async fn a_tee_presents_one_result_twice() {
+        use crate::adaptor::{Controller, JsonPresenter, PresenterA, TeePresenter};
 
-    pub struct Handler {
-        repo: DataRepository
+        let container = Container::new(InMemoryRepository::new());
+
+        let (view, json) = Controller::new(TeePresenter(PresenterA, JsonPresenter::new(false)))
+            .capture(dto("a", "A"))
+            .handle(|input| container.create(input))
+            .await;
+
+        assert_eq!(view.unwrap().id, "a");
+        let json: serde_json::Value = serde_json::from_str(&json.unwrap()).unwrap();
+        assert_eq!(json["id"], "a");
+        assert_eq!(container.repo.count().await.unwrap(), 1);
     }
-    impl Handler {
-        pub fn init() -> Self {
-            Self { repo: DataRepository(Pool) }
-        }
+
+    #[tokio::test]
+    async fn updating_an_existing_id_reports_true() {
+        let repo = InMemoryRepository::new();
+        repo.create(&Data::new("a", "A")).await.unwrap();
+        assert!(repo.update(&Data::new("a", "B"), 0).await.unwrap());
     }
-    impl DependOnRepository for Handler {
-        type Repository = DataRepository;
-        fn repository(&self) -> &Self::Repository {
-            &self.repo
-        }
+
+    #[tokio::test]
+    async fn updating_a_missing_id_reports_false() {
+        let repo = InMemoryRepository::new();
+        assert!(!repo.update(&Data::new("a", "B"), 0).await.unwrap());
     }
-    impl DependOnCreateDataService for Handler {
-        type CreateDataService = Self;
-        fn create_simple_data_service(&self) -> &Self::CreateDataService {
-            self
+
+    #[tokio::test]
+    async fn bulk_delete_counts_only_existing_ids() {
+        let container = Container::new(InMemoryRepository::new());
+        for id in ["a", "b"] {
+            container.repo.create(&Data::new(id, "x")).await.unwrap();
         }
+
+        let ids = ["a", "missing", "b", "gone"].map(String::from).to_vec();
+        assert_eq!(container.delete_many(ids).await.unwrap(), 2);
+        assert_eq!(container.repo.count().await.unwrap(), 0);
     }
-}
 
-/// A.k.a Presentation Layer
-pub mod adaptor {
-    use std::{marker::PhantomData, future::IntoFuture};
+    #[cfg(feature = "redact")]
+    #[test]
+    fn debug_masks_the_name_but_the_getter_does_not() {
+        let data = Data::new("a", "secret");
+        let debug = format!("{data:?}");
+        assert!(!debug.contains("secret"), "{debug}");
+        assert!(debug.contains("***"), "{debug}");
+        assert_eq!(data.name().as_ref(), "secret");
+    }
 
-    use crate::application::DataDto;
+    #[tokio::test]
+    async fn the_read_model_derives_its_display_name() {
+        let container = Container::new(InMemoryRepository::new());
+        container.repo.create(&Data::new("a", "Alice")).await.unwrap();
 
-    pub trait InPort<I>: 'static + Sync + Send {
-        type Dto;
-        fn emit(&self, input: I) -> Self::Dto;
+        let model = container.get_read_model("a".to_string()).await.unwrap();
+
+        assert_eq!(model.display_name, "Alice (a)");
     }
 
-    pub trait OutPort<I>: 'static + Sync + Send {
-        type ViewModel;
-        fn emit(&self, input: I) -> Self::ViewModel;
+    #[test]
+    fn a_kernel_error_propagates_into_anyhow_with_its_message() {
+        fn load() -> anyhow::Result<DataId> {
+            Ok(DataId::new("")?)
+        }
+
+        let e = load().unwrap_err();
+        assert_eq!(e.to_string(), "validation error: id must not be empty");
+        assert!(matches!(e.downcast_ref::<KernelError>(), Some(KernelError::Validation(_))));
     }
 
-    #[derive(Debug)]
-    pub struct PresentationalDataA {
-        id: String,
-        name: String
+    #[tokio::test]
+    async fn a_failing_item_does_not_abort_the_batch() {
+        use crate::adaptor::{Controller, PresenterA};
+
+        let container = Container::new(InMemoryRepository::new());
+
+        let views = Controller::new(PresenterA)
+            .capture_many(vec![dto("a", "A"), dto("b", " "), dto("c", "C")])
+            .handle(|input| container.create(input))
+            .await;
+
+        assert_eq!(views.len(), 3);
+        assert_eq!(views[0].as_ref().unwrap().id, "a");
+        assert!(matches!(views[1], Err(AppError::Validation(_))));
+        assert_eq!(views[2].as_ref().unwrap().id, "c");
     }
-    
-    pub struct PresenterA;
-    
-    impl OutPort<Result<DataDto, u64>> for PresenterA {
-        type ViewModel = Result<PresentationalDataA, u64>;
-        fn emit(&self, input: Result<DataDto, u64>) -> Self::ViewModel {
-            match input {
-                Ok(input) => {
-                    Ok(PresentationalDataA {
-                        id: input.id,
-                        name: input.name
-                    })
-                },
-                Err(code) => {
-                    Err(code)
+
+    #[tokio::test]
+    async fn capture_many_never_exceeds_its_concurrency_limit() {
+        use std::sync::atomic::AtomicUsize;
+
+        use crate::adaptor::{Controller, PresenterA};
+
+        let (running, peak) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        let inputs = (0..6).map(|n| dto(&format!("id-{n}"), "x")).collect::<Vec<_>>();
+
+        let views = Controller::new(PresenterA)
+            .capture_many(inputs)
+            .max_concurrency(2)
+            .handle(|input: DataDto| {
+                let (running, peak) = (&running, &peak);
+                async move {
+                    peak.fetch_max(running.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                    tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    Ok(input)
                 }
-            }
-        }
+            }).await;
+
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        let ids = views.into_iter().map(|view| view.unwrap().id).collect::<Vec<_>>();
+        assert_eq!(ids, (0..6).map(|n| format!("id-{n}")).collect::<Vec<_>>());
     }
-    
-    pub struct PresenterB;
-    
-    impl OutPort<Result<DataDto, u64>> for PresenterB {
-        type ViewModel = Result<String, u64>;
-        fn emit(&self, input: Result<DataDto, u64>) -> Self::ViewModel {
-            match input {
-                Ok(input) => {
-                    Ok(format!("{:?}", input))
-                },
-                Err(code) => {
-                    Err(code)
-                }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn the_mock_can_be_reset_between_service_calls() {
+        let container = Container::new(crate::testing::MockRepository::new());
+        container.create(dto("a", "A")).await.unwrap();
+
+        container.repository().reset();
+        container.create(dto("b", "B")).await.unwrap();
+
+        let calls = container.repo.calls();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].id().as_ref(), "b");
+    }
+
+    #[test]
+    fn csv_quotes_names_with_commas_and_quotes() {
+        use crate::adaptor::{CsvPresenter, OutPort};
+
+        let csv = CsvPresenter.emit(Ok(vec![dto("a", r#"Smith, "Jo""#)])).unwrap();
+
+        assert_eq!(csv, "id,name,version\r\na,\"Smith, \"\"Jo\"\"\",0\r\n");
+    }
+
+    #[tokio::test]
+    async fn a_failing_presenter_surfaces_through_try_handle() {
+        use crate::adaptor::{_Controller, FallibleOutPort};
+
+        struct RefusingPresenter;
+
+        impl FallibleOutPort<Result<DataDto, AppError>> for RefusingPresenter {
+            type ViewModel = String;
+            type Error = &'static str;
+            fn try_emit(&self, _input: Result<DataDto, AppError>) -> Result<String, &'static str> {
+                Err("cannot render")
             }
         }
+
+        let res = _Controller::new(Identity, RefusingPresenter)
+            .transform(dto("a", "A"))
+            .try_handle(|input| async { Ok(input) })
+            .await;
+
+        assert_eq!(res, Err("cannot render"));
     }
 
+    #[tokio::test]
+    async fn fail_fast_reports_a_failed_secondary_write() {
+        let repo = DualWriteRepository::new(ProbeRepository::default(), ProbeRepository::failing_creates(1), DualWritePolicy::FailFast);
+        let data = Data::new("a", "A");
+        assert!(matches!(repo.create(&data).await, Err(KernelError::Driver(_))));
+        assert!(repo.primary().find_by_id("a").await.unwrap().is_some());
+        assert!(repo.secondary().find_by_id("a").await.unwrap().is_none());
+    }
 
-    pub struct _Controller<T, P, I, D, O> {
-        transformer: T,
-        presenter: P,
-        _in: PhantomData<I>,
-        _trans: PhantomData<D>,
-        _out: PhantomData<O>
+    #[tokio::test]
+    async fn best_effort_logs_a_failed_secondary_write_and_succeeds() {
+        let logged = Arc::new(Mutex::new(Vec::<String>::new()));
+        let sink = {
+            let logged = Arc::clone(&logged);
+            move |message: &str| logged.lock().unwrap().push(message.to_string())
+        };
+        let repo = DualWriteRepository::new(ProbeRepository::default(), ProbeRepository::failing_creates(1), DualWritePolicy::BestEffort)
+            .with_sink(sink);
+        let data = Data::new("a", "A");
+        repo.create(&data).await.unwrap();
+        assert!(repo.primary().find_by_id("a").await.unwrap().is_some());
+        assert!(repo.secondary().find_by_id("a").await.unwrap().is_none());
+        assert_eq!(*logged.lock().unwrap(), vec!["secondary write failed: driver error: create refused".to_string()]);
     }
 
-    impl<T, P, I, D, O> _Controller<T, P, I, D, O>
-        where T: InPort<I, Dto = D>,
-              P: OutPort<O>
-    {
-        pub fn new(transformer: T, presenter: P) -> Self {
-            Self { transformer, presenter, _in: PhantomData, _trans: PhantomData, _out: PhantomData }
-        }
+    fn batch(len: usize) -> Vec<DataDto> {
+        (0..len).map(|i| dto(&format!("id-{i}"), "name")).collect()
+    }
 
-        pub fn transform(self, input: I) -> Transformed<T, P, I, D, O> {
-            Transformed { trans_input: self.transformer.emit(input), controller: self, _in: PhantomData, _out: PhantomData }
-        }
+    #[tokio::test]
+    async fn a_batch_over_max_batch_is_rejected_before_any_write() {
+        let container = Container::new(InMemoryRepository::new());
+        let res = container.create_many(batch(1001)).await;
+        assert!(matches!(res, Err(AppError::TooLarge { len: 1001, max: 1000 })));
+        assert_eq!(container.repo.count().await.unwrap(), 0);
+    }
 
-        fn present(self) -> P {
-            self.presenter
-        }
+    #[tokio::test]
+    async fn a_batch_of_max_batch_is_accepted() {
+        let container = Container::new(InMemoryRepository::new());
+        let results = container.create_many(batch(1000)).await.unwrap();
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(container.repo.count().await.unwrap(), 1000);
     }
 
-    pub struct Transformed<T, P, I, D, O> {
-        controller: _Controller<T, P, I, D, O>,
-        trans_input: D,
-        _in: PhantomData<I>,
-        _out: PhantomData<O>
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn a_shared_mock_serves_as_a_service_repository() {
+        let mock = Arc::new(crate::testing::MockRepository::new());
+        let container = Container::new(Arc::clone(&mock));
+        container.create(dto("a", "A")).await.unwrap();
+
+        let calls = mock.calls();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].id().as_ref(), "a");
     }
 
-    impl<T, P, I, D, O> Transformed<T, P, I, D, O>
-        where T: InPort<I, Dto = D>,
-              P: OutPort<O>
-    {
-        pub async fn handle<F, Fut>(self, f: F) -> P::ViewModel
-            where F: FnOnce(D) -> Fut,
-                  Fut: IntoFuture<Output = O>
-        {
-            self.controller.present().emit(f(self.trans_input).await)
-        }
+    #[tokio::test]
+    async fn a_form_with_an_empty_name_is_presented_without_running_the_handler() {
+        use crate::{adaptor::{Controller, PresenterA}, UserInputForm};
+
+        let called = AtomicBool::new(false);
+        let res = Controller::new(PresenterA)
+            .try_capture(UserInputForm { id: "a".to_string(), name: String::new() })
+            .handle(|input: DataDto| async {
+                called.store(true, Ordering::SeqCst);
+                Ok(input)
+            }).await;
+
+        assert!(matches!(res, Err(AppError::Validation(_))));
+        assert!(!called.load(Ordering::SeqCst));
     }
 
+    #[tokio::test]
+    async fn update_fields_changes_only_the_patched_name() {
+        let repo = InMemoryRepository::new();
+        let original = Data::new("a", "old").with_extra([("k".to_string(), "v".to_string())].into());
+        repo.create(&original).await.unwrap();
 
-    pub struct Controller<P, D> {
-        presenter: P,
-        _presenter_input: PhantomData<D>
+        let patch = DataPatch { name: Some("new".to_string()) };
+        assert!(repo.update_fields("a", patch).await.unwrap());
+
+        let stored = repo.find_by_id("a").await.unwrap().unwrap();
+        assert_eq!(stored.name().as_ref(), "new");
+        assert_eq!(stored.id(), original.id());
+        assert_eq!(stored.created_at(), original.created_at());
+        assert_eq!(stored.extra(), original.extra());
     }
 
-    impl<P: OutPort<D>, D> Controller<P, D> {
-        pub fn new(presenter: P) -> Self {
-            Self { presenter, _presenter_input: PhantomData }
+    #[tokio::test]
+    async fn a_defaulted_presenter_runs_through_a_controller() {
+        use crate::adaptor::{Controller, PresenterA};
+
+        fn defaulted<P: Default>() -> P {
+            P::default()
         }
 
-        pub fn capture<R: Into<N>, N>(self, input: R) -> Captured<R, N, D, P> {
-            Captured { controller: self, input, _need: PhantomData, _conv: PhantomData }
+        let view = Controller::new(defaulted::<PresenterA>())
+            .capture(dto("a", "A"))
+            .handle(|input: DataDto| async { Ok(input) })
+            .await
+            .unwrap();
+
+        assert_eq!(view.id, "a");
+        assert_eq!(view.name, "A");
+    }
+
+    #[tokio::test]
+    async fn get_many_skips_missing_ids() {
+        let container = Container::new(InMemoryRepository::new());
+        container.create(dto("a", "A")).await.unwrap();
+        container.create(dto("c", "C")).await.unwrap();
+
+        let found = container.get_many(vec!["a".to_string(), "b".to_string(), "c".to_string()]).await.unwrap();
+
+        let mut ids: Vec<_> = found.into_iter().map(|dto| dto.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec!["a", "c"]);
+    }
+
+    #[tokio::test]
+    async fn handle_with_hands_the_metadata_to_the_presenter() {
+        use crate::adaptor::{_Controller, MetaOutPort};
+
+        struct CacheFlagPresenter;
+        impl MetaOutPort<Result<DataDto, AppError>, bool> for CacheFlagPresenter {
+            type ViewModel = Result<(String, bool), AppError>;
+            fn emit(&self, input: Result<DataDto, AppError>, cache_hit: bool) -> Self::ViewModel {
+                input.map(|dto| (dto.id, cache_hit))
+            }
         }
+
+        let view = _Controller::new(Identity, CacheFlagPresenter)
+            .transform(dto("a", "A"))
+            .handle_with(|input| async { (Ok(input), true) })
+            .await;
+
+        assert_eq!(view.unwrap(), ("a".to_string(), true));
     }
 
-    pub struct Captured<R, N, D, P> {
-        controller: Controller<P, D>,
-        input: R,
-        _need: PhantomData<N>,
-        _conv: PhantomData<D>
+    /// A repository over `POSTGRES_TEST_URL`, creating the `data` table there if needed; `None` if it is unset.
+    #[cfg(feature = "postgres")]
+    async fn postgres() -> Option<crate::driver::PostgresRepository> {
+        let url = std::env::var("POSTGRES_TEST_URL").ok()?;
+        let pool = Pool::connect_postgres(&url, 2).await.unwrap();
+        let schema = sqlx::PgPool::connect(&url).await.unwrap();
+        sqlx::query("CREATE TABLE IF NOT EXISTS data (
+            id         TEXT PRIMARY KEY,
+            name       TEXT NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL,
+            version    BIGINT NOT NULL,
+            extra      JSONB NOT NULL
+        )").execute(&schema).await.unwrap();
+        Some(crate::driver::PostgresRepository::new(pool).unwrap())
     }
-    
-    impl<R, N, D, P> Captured<R, N, D, P>
-        where R: Into<N>,
-              P: OutPort<D>
-    {
-        pub async fn handle<F, Fut>(self, f: F) -> P::ViewModel
-            where F: Fn(N) -> Fut,
-                  Fut: IntoFuture<Output = D>
-        {
-            self.controller.presenter.emit(f(self.input.into()).await)
+
+    #[cfg(feature = "postgres")]
+    #[tokio::test]
+    async fn postgres_reads_back_what_it_created() {
+        let Some(repo) = postgres().await else {
+            return;
+        };
+        let id = format!("test-{}", std::process::id());
+        let data = Data::new(id.as_str(), "A");
+        repo.create(&data).await.unwrap();
+
+        let found = repo.find_by_id(&id).await.unwrap().unwrap();
+        repo.delete(&id).await.unwrap();
+        assert_eq!(found.id(), data.id());
+        assert_eq!(found.name(), data.name());
+    }
+
+    #[cfg(feature = "postgres")]
+    #[tokio::test]
+    async fn postgres_bulk_deletes_report_the_removed_ids_in_input_order() {
+        let Some(repo) = postgres().await else {
+            return;
+        };
+        let [a, b, missing] = ["a", "b", "missing"].map(|id| format!("delete-{}-{id}", std::process::id()));
+        for id in [&a, &b] {
+            repo.create(&Data::new(id.as_str(), "x")).await.unwrap();
         }
+
+        let deleted = repo.delete_many(&[b.clone(), missing, a.clone(), b.clone()]).await.unwrap();
+        assert_eq!(deleted, vec![b, a]);
     }
-}
 
-use std::future::IntoFuture;
+    #[cfg(feature = "postgres")]
+    #[tokio::test]
+    async fn postgres_bulk_inserts_report_conflicts_per_row() {
+        let Some(repo) = postgres().await else {
+            return;
+        };
+        let [a, b] = ["a", "b"].map(|id| format!("bulk-{}-{id}", std::process::id()));
+        repo.create(&Data::new(a.as_str(), "A")).await.unwrap();
 
-use adaptor::{_Controller as ControllerA, Controller as ControllerB, InPort, PresenterA, PresenterB};
-use application::{DependOnCreateDataService, CreateDataService};
-use inject::Handler;
+        let results = repo.create_many_returning(&[Data::new(a.as_str(), "A"), Data::new(b.as_str(), "B"), Data::new(b.as_str(), "B")]).await;
+        repo.delete_many(&[a.clone(), b.clone()]).await.unwrap();
 
-use crate::application::DataDto;
+        let results = results.unwrap();
+        assert!(matches!(results[0], Err(KernelError::Conflict)));
+        assert_eq!(results[1].as_ref().unwrap().id().as_ref(), b);
+        assert!(matches!(results[2], Err(KernelError::Conflict)));
+    }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let handler = Handler::init();
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite_in_memory_reads_back_what_it_created() {
+        let repo = crate::driver::SqliteRepository::in_memory().await.unwrap();
+        let data = Data::new("a", "A");
+        repo.create(&data).await.unwrap();
 
-    #[derive(Clone)]
-    struct UserInputForm {
-        pub id: String,
-        pub name: String
+        let found = repo.find_by_id("a").await.unwrap().unwrap();
+        assert_eq!(found.id(), data.id());
+        assert_eq!(found.name(), data.name());
     }
 
-    pub struct TransformerA;
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite_bulk_inserts_report_conflicts_per_row() {
+        let repo = crate::driver::SqliteRepository::in_memory().await.unwrap();
+        repo.create(&Data::new("a", "A")).await.unwrap();
 
-    impl InPort<UserInputForm> for TransformerA {
-        type Dto = DataDto;
-        fn emit(&self, input: UserInputForm) -> Self::Dto {
-            Self::Dto {
-                id: input.id,
-                name: input.name
-            }
-        }
+        let results = repo.create_many_returning(&[Data::new("a", "A"), Data::new("b", "B"), Data::new("b", "B")]).await.unwrap();
+
+        assert!(matches!(results[0], Err(KernelError::Conflict)));
+        assert_eq!(results[1].as_ref().unwrap().id().as_ref(), "b");
+        assert!(matches!(results[2], Err(KernelError::Conflict)));
+        assert_eq!(repo.count().await.unwrap(), 2);
     }
 
-    impl From<UserInputForm> for DataDto {
-        fn from(value: UserInputForm) -> Self {
-            Self {
-                id: value.id,
-                name: value.name
-            }
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite_bulk_deletes_report_the_removed_ids_in_input_order() {
+        let repo = crate::driver::SqliteRepository::in_memory().await.unwrap();
+        for id in ["a", "b"] {
+            repo.create(&Data::new(id, "x")).await.unwrap();
         }
+
+        let ids = ["b", "missing", "a", "b"].map(String::from);
+        assert_eq!(repo.delete_many(&ids).await.unwrap(), vec!["b", "a"]);
+        assert_eq!(repo.count().await.unwrap(), 0);
     }
 
-    let input = UserInputForm {
-        id: "abc123".to_string(),
-        name: "test man".to_string()
-    };
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite_maps_a_duplicate_id_to_conflict() {
+        let repo = crate::driver::SqliteRepository::in_memory().await.unwrap();
+        repo.create(&Data::new("a", "A")).await.unwrap();
+        assert!(matches!(repo.create(&Data::new("a", "B")).await, Err(KernelError::Conflict)));
+    }
 
-    let res = ControllerA::new(TransformerA, PresenterA)
-        .transform(input.clone())
-        .handle(|input| async { // <- ここで型が推論される
-            handler.create_simple_data_service()
-                .create(input)
-                .await
-        }).await;
-    println!("{:?}", res);
+    #[tokio::test]
+    async fn create_presents_the_timestamp_the_repository_stamped() {
+        let stamped = OffsetDateTime::UNIX_EPOCH + Duration::days(1);
+        let container = Container::new(ProbeRepository::stamping(stamped))
+            .with_clock(FixedClock(OffsetDateTime::UNIX_EPOCH));
 
-    let res = ControllerA::new(TransformerA, PresenterB)
-        .transform(input.clone())
-        .handle(|input| async {
-            handler.create_simple_data_service()
-                .create(input)
-                .await
-        }).await;
-    println!("{:?}", res);
+        let created = container.create(dto("a", "A")).await.unwrap();
 
-    let res = ControllerB::new(PresenterA)
-        .capture(input.clone())
-        .handle(|input| async {
-            handler.create_simple_data_service()
-                .create(input) // <- ここで型が推論される
-                .await
-        }).await;
-    println!("{:?}", res);
-    
-    let res = ControllerB::new(PresenterB)
-        .capture(input)
-        .handle(|input| async {
-            handler.create_simple_data_service()
-                .create(input)
-                .await  
-        }).await;
-    println!("{:?}", res);
+        assert_eq!(created.created_at, Some(stamped));
+    }
 
-    Ok(())
-}
\ No newline at end of file
+    #[test]
+    fn handle_sync_presents_the_closure_output_through_presenter_a() {
+        use crate::adaptor::{_Controller, PresenterA};
+
+        let view = _Controller::new(Identity, PresenterA)
+            .transform(dto("a", "A"))
+            .handle_sync(Ok)
+            .unwrap();
+
+        assert_eq!(view.id, "a");
+        assert_eq!(view.name, "A");
+    }
+
+    #[tokio::test]
+    async fn generated_ids_follow_the_sequence() {
+        let container = Container::new(InMemoryRepository::new());
+
+        let first = container.create_with_generated_id("A".to_string()).await.unwrap();
+        let second = container.create_with_generated_id("B".to_string()).await.unwrap();
+
+        assert_eq!(first.id, "id-1");
+        assert_eq!(second.id, "id-2");
+        assert!(container.repo.find_by_id("id-2").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn close_waits_for_an_open_transaction() {
+        let pool = Pool::in_memory();
+        let tx = pool.begin().await.unwrap();
+        let mut closed = Box::pin(pool.close());
+
+        assert!(tokio::time::timeout(std::time::Duration::from_millis(20), &mut closed).await.is_err());
+        tx.commit().await.unwrap();
+        closed.await;
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite_streams_every_row_across_pages() {
+        use futures::TryStreamExt;
+
+        let repo = crate::driver::SqliteRepository::in_memory().await.unwrap();
+        let rows = (0..150).map(|i| Data::new(format!("id-{i:03}"), "x")).collect::<Vec<_>>();
+        repo.create_many(&rows).await.unwrap();
+
+        let streamed: Vec<Data> = repo.stream_all().try_collect().await.unwrap();
+        assert_eq!(streamed.len(), 150);
+        assert_eq!(streamed.last().unwrap().id().as_ref(), "id-149");
+    }
+}
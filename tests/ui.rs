@@ -0,0 +1,6 @@
+#[test]
+fn ui() {
+    let t = trybuild::TestCases::new();
+    t.pass("tests/ui/in_port_map_*_pass.rs");
+    t.compile_fail("tests/ui/in_port_map_*_fail.rs");
+}
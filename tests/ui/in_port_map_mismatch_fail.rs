@@ -0,0 +1,9 @@
+use controller_test::in_port_map;
+
+pub struct Form { pub id: String, pub display_name: String }
+pub struct Dto { pub id: String, pub name: String }
+pub struct Port;
+
+in_port_map!(impl InPort<Form> for Port => Dto { id, name });
+
+fn main() {}
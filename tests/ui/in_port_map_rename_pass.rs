@@ -0,0 +1,15 @@
+use controller_test::{in_port_map, port::InPort};
+
+pub struct Form { pub id: String, pub display_name: String }
+pub struct Dto { pub id: String, pub name: String, pub version: i64 }
+pub struct Port;
+
+in_port_map!(impl InPort<Form> for Port => Dto {
+    id, name = display_name;
+    version: 0
+});
+
+fn main() {
+    let dto = Port.emit(Form { id: "a".to_string(), display_name: "A".to_string() });
+    assert_eq!((dto.id.as_str(), dto.name.as_str(), dto.version), ("a", "A", 0));
+}